@@ -0,0 +1,264 @@
+use crate::glob;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use sha2::Digest;
+use sha2::Sha256;
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::path;
+
+const DEFAULT_MAX_FILE_BYTES: u64 = 1024 * 1024;
+const DEFAULT_MAX_TOTAL_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    pub max_file_bytes: Option<u64>,
+    pub max_total_bytes: Option<u64>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    pub content: String,
+}
+
+// Collect files in the work path matching any of the patterns
+pub fn collect(
+    work_path: &path::Path,
+    patterns: &[String],
+    limits: &Limits,
+) -> Result<Vec<Artifact>, Error> {
+    if patterns.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let max_file_bytes = limits.max_file_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES);
+    let max_total_bytes = limits.max_total_bytes.unwrap_or(DEFAULT_MAX_TOTAL_BYTES);
+    let mut total_bytes = 0;
+
+    let mut files = Vec::new();
+    list_files(work_path, &mut files)?;
+    files.sort();
+
+    let mut artifacts = Vec::new();
+
+    for file in files {
+        let relative_file_path = file.strip_prefix(work_path).map_err(Error::StripWorkPath)?;
+
+        // The home and cache directories of the commands are not artifacts
+        if relative_file_path.starts_with(crate::ENV_DIR) {
+            continue;
+        }
+
+        let relative_path = relative_file_path.to_string_lossy().to_string();
+
+        let is_match = patterns
+            .iter()
+            .any(|pattern| glob::is_match(pattern, &relative_path));
+
+        if !is_match {
+            continue;
+        }
+
+        // Processes that outlived the command may have replaced the file since it was listed,
+        // so only the opened file is checked and read
+        let mut artifact_file = open_no_follow(work_path, relative_file_path)
+            .map_err(|err| Error::ReadFile(file.clone(), err))?;
+
+        let metadata = artifact_file
+            .metadata()
+            .map_err(|err| Error::ReadFile(file.clone(), err))?;
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let size = metadata.len();
+
+        if size > max_file_bytes {
+            return Err(Error::FileTooLarge(relative_path, size, max_file_bytes));
+        }
+
+        // The file may still grow while it's read
+        let mut content = Vec::new();
+        artifact_file
+            .by_ref()
+            .take(max_file_bytes + 1)
+            .read_to_end(&mut content)
+            .map_err(|err| Error::ReadFile(file.clone(), err))?;
+
+        let size = content.len() as u64;
+
+        if size > max_file_bytes {
+            return Err(Error::FileTooLarge(relative_path, size, max_file_bytes));
+        }
+
+        total_bytes += size;
+
+        if total_bytes > max_total_bytes {
+            return Err(Error::TotalTooLarge(max_total_bytes));
+        }
+
+        artifacts.push(to_artifact(relative_path, &content));
+    }
+
+    Ok(artifacts)
+}
+
+pub fn to_artifact(path: String, content: &[u8]) -> Artifact {
+    Artifact {
+        path,
+        size: content.len() as u64,
+        sha256: format!("{:x}", Sha256::digest(content)),
+        content: BASE64_STANDARD.encode(content),
+    }
+}
+
+// Open the file below the work path without following symlinks in any component.
+// Non-blocking, so a file that was replaced by a fifo doesn't block the open
+fn open_no_follow(work_path: &path::Path, relative_path: &path::Path) -> io::Result<fs::File> {
+    let mut file = fs::File::open(work_path)?;
+
+    let mut components = relative_path.iter().peekable();
+
+    while let Some(component) = components.next() {
+        let name = ffi::CString::new(component.as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let flags = if components.peek().is_some() {
+            libc::O_RDONLY | libc::O_DIRECTORY
+        } else {
+            libc::O_RDONLY | libc::O_NONBLOCK
+        };
+
+        let fd = unsafe {
+            libc::openat(
+                file.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        file = unsafe { fs::File::from_raw_fd(fd) };
+    }
+
+    Ok(file)
+}
+
+// Recursively list regular files, symlinks are not followed
+fn list_files(dir: &path::Path, files: &mut Vec<path::PathBuf>) -> Result<(), Error> {
+    let entries = fs::read_dir(dir).map_err(|err| Error::ReadDir(dir.to_path_buf(), err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| Error::ReadDir(dir.to_path_buf(), err))?;
+
+        let file_type = entry
+            .file_type()
+            .map_err(|err| Error::ReadDir(dir.to_path_buf(), err))?;
+
+        if file_type.is_dir() {
+            list_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ReadDir(path::PathBuf, io::Error),
+    ReadFile(path::PathBuf, io::Error),
+    StripWorkPath(path::StripPrefixError),
+    FileTooLarge(String, u64, u64),
+    TotalTooLarge(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ReadDir(dir_path, err) => {
+                write!(
+                    f,
+                    "Failed to read dir: '{}'. {}",
+                    dir_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::ReadFile(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to read file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::StripWorkPath(err) => {
+                write!(f, "Failed to strip work path of file. {}", err)
+            }
+
+            Error::FileTooLarge(file_path, size, max_size) => {
+                write!(
+                    f,
+                    "Artifact '{}' is {} bytes which exceeds the limit of {} bytes",
+                    file_path, size, max_size
+                )
+            }
+
+            Error::TotalTooLarge(max_size) => {
+                write!(
+                    f,
+                    "Artifacts exceed the total size limit of {} bytes",
+                    max_size
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn open_no_follow_rejects_symlinks() {
+        let work_path = std::env::temp_dir().join(format!("artifact-test-{}", std::process::id()));
+        fs::create_dir_all(work_path.join("dir")).unwrap();
+        fs::write(work_path.join("dir/file.txt"), "content").unwrap();
+        symlink(work_path.join("dir/file.txt"), work_path.join("file-link")).unwrap();
+        symlink(work_path.join("dir"), work_path.join("dir-link")).unwrap();
+
+        let mut content = String::new();
+        open_no_follow(&work_path, path::Path::new("dir/file.txt"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+
+        let file_link = open_no_follow(&work_path, path::Path::new("file-link"));
+        let dir_link = open_no_follow(&work_path, path::Path::new("dir-link/file.txt"));
+
+        fs::remove_dir_all(&work_path).unwrap();
+
+        assert_eq!(content, "content");
+        assert!(file_link.is_err());
+        assert!(dir_link.is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix;
+use std::path;
+
+// Symlinks followed when resolving a path, like the limit of Linux
+const MAX_SYMLINKS: usize = 40;
+
+// Validate the entries of a bootstrap archive from the output of
+// `tar --numeric-owner --quoting-style=c -tvzf`, i.e.
+// `-rw-r--r-- 0/0      12 2024-01-31 12:00 "main.py"`
+// `lrwxrwxrwx 0/0       0 2024-01-31 12:00 "lib" -> "../lib"`
+// The names are quoted and escaped, so a name can't be mistaken for a link target,
+// and the fields before the name don't contain quotes
+pub fn validate_listing(listing: &str) -> Result<(), Error> {
+    let entries = listing
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_entry)
+        .collect::<Result<Vec<Entry>, Error>>()?;
+
+    // Paths are resolved against all symlinks of the archive, so chains of links
+    // and files written through a linked directory are checked regardless of the order
+    let symlinks = entries
+        .iter()
+        .filter_map(|entry| match &entry.kind {
+            EntryKind::Symlink(target) => Some((
+                path::PathBuf::from(&entry.name),
+                path::PathBuf::from(target),
+            )),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    entries
+        .iter()
+        .try_for_each(|entry| validate_entry(entry, &symlinks))
+}
+
+struct Entry {
+    name: String,
+    kind: EntryKind,
+}
+
+enum EntryKind {
+    Other,
+    Symlink(String),
+    Hardlink(String),
+}
+
+fn parse_entry(line: &str) -> Result<Entry, Error> {
+    let invalid_entry = || Error::InvalidEntry(line.to_string());
+
+    let entry_type = line.chars().next().unwrap_or('-');
+    let name_start = line.find('"').ok_or_else(invalid_entry)?;
+    let (name, rest) = parse_quoted(&line[name_start..]).ok_or_else(invalid_entry)?;
+
+    let kind = match entry_type {
+        'b' | 'c' => return Err(Error::DeviceNode(name)),
+
+        'l' => EntryKind::Symlink(parse_target(rest, " -> ").ok_or_else(invalid_entry)?),
+
+        'h' => EntryKind::Hardlink(parse_target(rest, " link to ").ok_or_else(invalid_entry)?),
+
+        _ if rest.is_empty() => EntryKind::Other,
+
+        _ => return Err(invalid_entry()),
+    };
+
+    validate_name(&name)?;
+
+    Ok(Entry { name, kind })
+}
+
+fn validate_entry(
+    entry: &Entry,
+    symlinks: &HashMap<path::PathBuf, path::PathBuf>,
+) -> Result<(), Error> {
+    let name = path::Path::new(&entry.name);
+    let parent_dir = name.parent().unwrap_or(path::Path::new(""));
+
+    // The entry itself is written into its parent directory, which may be a symlink
+    if resolve(parent_dir, symlinks).is_none() {
+        return Err(Error::WrittenOutside(entry.name.clone()));
+    }
+
+    match &entry.kind {
+        EntryKind::Other => Ok(()),
+
+        // The target is relative to the directory of the link
+        EntryKind::Symlink(target) => {
+            if path::Path::new(target).is_absolute()
+                || resolve(&parent_dir.join(target), symlinks).is_none()
+            {
+                return Err(Error::SymlinkOutside(entry.name.clone(), target.clone()));
+            }
+
+            Ok(())
+        }
+
+        // The target is relative to the extraction directory
+        EntryKind::Hardlink(target) => {
+            validate_name(target)?;
+
+            if resolve(path::Path::new(target), symlinks).is_none() {
+                return Err(Error::HardlinkOutside(entry.name.clone(), target.clone()));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+// The quoted target after the separator has to be the rest of the line
+fn parse_target(text: &str, separator: &str) -> Option<String> {
+    let (target, rest) = parse_quoted(text.strip_prefix(separator)?)?;
+
+    Some(target).filter(|_| rest.is_empty())
+}
+
+// Parse a C style quoted string at the start of the text,
+// returns the unescaped string and the text after the closing quote
+fn parse_quoted(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 2..])),
+
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                '\\' => value.push('\\'),
+                '"' => value.push('"'),
+
+                // Octal and other escapes are kept as they are, they never stand for '/' or '.'
+                escaped => {
+                    value.push('\\');
+                    value.push(escaped);
+                }
+            },
+
+            _ => value.push(c),
+        }
+    }
+
+    None
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    if path::Path::new(name).is_absolute() {
+        return Err(Error::AbsolutePath(name.to_string()));
+    }
+
+    let has_parent_dir = path::Path::new(name)
+        .components()
+        .any(|component| component == path::Component::ParentDir);
+
+    if has_parent_dir {
+        return Err(Error::ParentDirectory(name.to_string()));
+    }
+
+    Ok(())
+}
+
+// Resolve a relative path against the symlinks of the archive, returns None if
+// it leads outside of the base directory or has too many levels of symlinks
+fn resolve(
+    file_path: &path::Path,
+    symlinks: &HashMap<path::PathBuf, path::PathBuf>,
+) -> Option<path::PathBuf> {
+    let mut resolved = path::PathBuf::new();
+    let mut pending = file_path
+        .components()
+        .map(|component| component.as_os_str().to_os_string())
+        .collect::<VecDeque<_>>();
+    let mut followed = 0;
+
+    while let Some(component) = pending.pop_front() {
+        match path::Path::new(&component).components().next() {
+            None | Some(path::Component::CurDir) => {}
+
+            Some(path::Component::ParentDir) => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+
+            Some(path::Component::Normal(name)) => {
+                resolved.push(name);
+
+                if let Some(target) = symlinks.get(&resolved) {
+                    followed += 1;
+
+                    if followed > MAX_SYMLINKS || target.is_absolute() {
+                        return None;
+                    }
+
+                    // The target replaces the link and is relative to the directory of the link
+                    resolved.pop();
+
+                    for target_component in target.components().rev() {
+                        pending.push_front(target_component.as_os_str().to_os_string());
+                    }
+                }
+            }
+
+            Some(path::Component::RootDir) | Some(path::Component::Prefix(_)) => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+// Copy the unpacked bootstrap files into the work path. fs::copy clones the
+// file content where the file system supports it, so this is cheaper than unpacking again
+pub fn copy_tree(source: &path::Path, target: &path::Path) -> Result<(), Error> {
+    fs::create_dir_all(target).map_err(|err| Error::Copy(target.to_path_buf(), err))?;
+
+    let entries = fs::read_dir(source).map_err(|err| Error::Copy(source.to_path_buf(), err))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| Error::Copy(source.to_path_buf(), err))?;
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+
+        let metadata = fs::symlink_metadata(&source_path)
+            .map_err(|err| Error::Copy(source_path.to_path_buf(), err))?;
+
+        let result = if metadata.file_type().is_symlink() {
+            fs::read_link(&source_path).and_then(|link| unix::fs::symlink(link, &target_path))
+        } else if metadata.is_dir() {
+            copy_tree(&source_path, &target_path)?;
+            fs::set_permissions(&target_path, metadata.permissions())
+        } else {
+            fs::copy(&source_path, &target_path).map(|_| ())
+        };
+
+        result.map_err(|err| Error::Copy(source_path.to_path_buf(), err))?;
+    }
+
+    Ok(())
+}
+
+pub enum Error {
+    InvalidEntry(String),
+    AbsolutePath(String),
+    ParentDirectory(String),
+    DeviceNode(String),
+    SymlinkOutside(String, String),
+    HardlinkOutside(String, String),
+    WrittenOutside(String),
+    Copy(path::PathBuf, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidEntry(line) => {
+                write!(f, "Failed to parse entry: '{}'", line)
+            }
+
+            Error::AbsolutePath(name) => {
+                write!(f, "Entry '{}' has an absolute path", name)
+            }
+
+            Error::ParentDirectory(name) => {
+                write!(f, "Entry '{}' contains '..'", name)
+            }
+
+            Error::DeviceNode(name) => {
+                write!(f, "Entry '{}' is a device node", name)
+            }
+
+            Error::SymlinkOutside(name, target) => {
+                write!(
+                    f,
+                    "Symlink '{}' points outside of the work path: '{}'",
+                    name, target
+                )
+            }
+
+            Error::HardlinkOutside(name, target) => {
+                write!(
+                    f,
+                    "Hardlink '{}' points outside of the work path: '{}'",
+                    name, target
+                )
+            }
+
+            Error::WrittenOutside(name) => {
+                write!(
+                    f,
+                    "Entry '{}' would be written outside of the work path through a symlink",
+                    name
+                )
+            }
+
+            Error::Copy(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to copy '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str) -> String {
+        format!("-rw-r--r-- 0/0      12 2024-01-31 12:00 \"{}\"", name)
+    }
+
+    fn symlink(name: &str, target: &str) -> String {
+        format!(
+            "lrwxrwxrwx 0/0       0 2024-01-31 12:00 \"{}\" -> \"{}\"",
+            name, target
+        )
+    }
+
+    fn validate(lines: &[String]) -> Result<(), Error> {
+        validate_listing(&lines.join("\n"))
+    }
+
+    #[test]
+    fn links_inside_are_valid() {
+        let listing = [
+            file("lib/main.py"),
+            symlink("current", "lib"),
+            symlink("lib/self", "."),
+            symlink("lib/up", "../current/main.py"),
+            file("current/other.py"),
+        ];
+
+        assert!(validate(&listing).is_ok());
+    }
+
+    #[test]
+    fn link_outside() {
+        let listing = [symlink("lib/up", "../..")];
+
+        assert!(matches!(
+            validate(&listing),
+            Err(Error::SymlinkOutside(_, _))
+        ));
+    }
+
+    #[test]
+    fn chained_links_outside() {
+        let listing = [symlink("a", "."), symlink("a/b", "../..")];
+
+        assert!(matches!(
+            validate(&listing),
+            Err(Error::SymlinkOutside(_, _))
+        ));
+
+        // The link that makes the target escape comes later in the listing
+        let listing = [symlink("c", "b/b/b/.."), symlink("b", ".")];
+
+        assert!(matches!(
+            validate(&listing),
+            Err(Error::SymlinkOutside(_, _))
+        ));
+    }
+
+    #[test]
+    fn file_written_through_link() {
+        let entry = Entry {
+            name: "a/x".to_string(),
+            kind: EntryKind::Other,
+        };
+        let symlinks = HashMap::from([(path::PathBuf::from("a"), path::PathBuf::from("../out"))]);
+
+        assert!(matches!(
+            validate_entry(&entry, &symlinks),
+            Err(Error::WrittenOutside(_))
+        ));
+    }
+
+    #[test]
+    fn link_loop() {
+        let listing = [symlink("a", "b"), symlink("b", "a"), file("a/x")];
+
+        assert!(matches!(
+            validate(&listing),
+            Err(Error::SymlinkOutside(_, _))
+        ));
+    }
+}
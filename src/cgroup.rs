@@ -0,0 +1,154 @@
+use crate::limits::Limits;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+use std::sync::atomic;
+use std::thread;
+use std::time::Duration;
+
+// cpu.max is the quota per period, both in microseconds
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+// How long to wait for the killed processes to leave the cgroup before it's removed
+const REMOVE_ATTEMPTS: u32 = 200;
+const REMOVE_INTERVAL: Duration = Duration::from_millis(5);
+
+static COUNT: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+
+// Transient cgroup of a single command, created below the cgroup given with --cgroup.
+// Unlike rlimits, the limits of a cgroup apply to the whole process tree of the command.
+// The cgroup and the processes that are left in it are removed when it's dropped
+pub struct Cgroup {
+    path: path::PathBuf,
+}
+
+// The parent has to be a cgroup v2 directory that is writable by the runner,
+// with the memory, cpu and pids controllers enabled in its cgroup.subtree_control
+pub fn create(parent: &path::Path, limits: &Limits) -> Result<Cgroup, Error> {
+    let name = format!(
+        "code-runner-{}-{}",
+        process::id(),
+        COUNT.fetch_add(1, atomic::Ordering::Relaxed)
+    );
+
+    let cgroup_path = parent.join(name);
+
+    fs::create_dir(&cgroup_path).map_err(|err| Error::Create(cgroup_path.to_path_buf(), err))?;
+
+    let cgroup = Cgroup { path: cgroup_path };
+
+    if let Some(bytes) = limits.memory_bytes {
+        cgroup.write("memory.max", &bytes.to_string())?;
+
+        // Without swap accounting there is no swap limit to set
+        if cgroup.path.join("memory.swap.max").exists() {
+            cgroup.write("memory.swap.max", "0")?;
+        }
+    }
+
+    if let Some(percent) = limits.cpu_percent {
+        // The kernel rejects quotas below 1ms
+        let quota = (percent * CPU_PERIOD_MICROS / 100).max(1000);
+        cgroup.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD_MICROS))?;
+    }
+
+    if let Some(count) = limits.nproc {
+        cgroup.write("pids.max", &count.to_string())?;
+    }
+
+    Ok(cgroup)
+}
+
+impl Cgroup {
+    // Opened before the fork, the child adds itself to the cgroup by writing 0 to it
+    pub fn open_procs(&self) -> Result<fs::File, Error> {
+        let procs_path = self.path.join("cgroup.procs");
+
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&procs_path)
+            .map_err(|err| Error::Open(procs_path, err))
+    }
+
+    // Highest memory usage of the processes in the cgroup, memory.peak needs Linux 5.19
+    pub fn peak_memory_bytes(&self) -> Option<u64> {
+        fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|content| content.trim().parse().ok())
+    }
+
+    // The kernel killed a process because the cgroup reached memory.max
+    pub fn oom_killed(&self) -> bool {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .find_map(|line| line.strip_prefix("oom_kill "))
+                    .and_then(|count| count.trim().parse::<u64>().ok())
+            })
+            .is_some_and(|count| count > 0)
+    }
+
+    fn write(&self, file_name: &str, value: &str) -> Result<(), Error> {
+        let file_path = self.path.join(file_name);
+        fs::write(&file_path, value).map_err(|err| Error::Write(file_path, err))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // cgroup.kill needs Linux 5.14, the process group was killed already either way
+        let _ = self.write("cgroup.kill", "1");
+
+        for _ in 0..REMOVE_ATTEMPTS {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+
+            thread::sleep(REMOVE_INTERVAL);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Create(path::PathBuf, io::Error),
+    Write(path::PathBuf, io::Error),
+    Open(path::PathBuf, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Create(cgroup_path, err) => {
+                write!(
+                    f,
+                    "Failed to create cgroup '{}'. {}",
+                    cgroup_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::Write(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to write cgroup file '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::Open(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to open cgroup file '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+        }
+    }
+}
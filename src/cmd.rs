@@ -1,29 +1,158 @@
+use crate::cgroup;
+use crate::limits::Limits;
+use std::ffi;
 use std::fmt;
 use std::io;
+use std::io::Read;
 use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
 use std::path;
 use std::process;
 use std::string;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
+// Seatbelt profile for sandbox-exec, %s is replaced with the work path
+#[cfg(target_os = "macos")]
+const SANDBOX_PROFILE: &str = r#"(version 1)
+(deny default)
+(allow process-fork)
+(allow process-exec)
+(allow signal (target same-sandbox))
+(allow sysctl-read)
+(allow mach-lookup)
+(allow ipc-posix-shm)
+(allow file-read*)
+(allow file-write*
+  (subpath "%s")
+  (subpath "/private/tmp")
+  (subpath "/private/var/folders")
+  (literal "/dev/null")
+  (literal "/dev/zero")
+  (literal "/dev/tty"))
+(allow file-ioctl (literal "/dev/tty"))
+"#;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Messages of runtimes that failed to allocate memory, matched against the lowercased stderr.
+// Any program can print them, so they are only a hint
+const OUT_OF_MEMORY_MESSAGES: &[&str] = &[
+    "out of memory",
+    "outofmemoryerror",
+    "memoryerror",
+    "bad_alloc",
+    "cannot allocate memory",
+    "failed to allocate memory",
+    "memory allocation of",
+];
+
+// strerror of EFBIG, lowercased. Any program can print it, so it's only a hint
+const FILE_TOO_LARGE_MESSAGE: &str = "file too large";
+
+// Process ids of the commands that are currently running, used to deliver signals from the kernel
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+// How long to wait for the output pipes to close after the command was killed,
+// they stay open if the command started processes that left its process group
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
 pub struct Options {
     pub work_path: path::PathBuf,
     pub command: String,
-    pub stdin: Option<String>,
+    pub stdin: Option<Stdin>,
+    // Close stdin after it was written, or keep it open until the command exits
+    pub close_stdin: bool,
+    pub limits: Limits,
+    pub umask: Option<u32>,
+    pub sandbox: bool,
+    pub env: Vec<(String, String)>,
+    pub output_events: bool,
+    // Root directory of the command, the work path must be inside it
+    pub chroot: Option<path::PathBuf>,
+    // How long the command gets to exit after SIGTERM before it's killed
+    pub term_grace_period: Duration,
+    // Run the command as this user instead of the user of the runner
+    pub user: Option<User>,
+    // Parent directory of the cgroup that is created for the command
+    pub cgroup: Option<path::PathBuf>,
+    // Run the command in a new network namespace without network access
+    pub isolate_network: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct User {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+// Stdin is either written all at once, or as a script of writes with a delay before each
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Stdin {
+    Text(String),
+    Script(Vec<StdinStep>),
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StdinStep {
+    pub data: String,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+impl Stdin {
+    pub fn size(&self) -> usize {
+        match self {
+            Stdin::Text(text) => text.len(),
+            Stdin::Script(steps) => steps.iter().map(|step| step.data.len()).sum(),
+        }
+    }
 }
 
 pub fn run(options: Options) -> Result<SuccessOutput, Error> {
     let now = Instant::now();
-    let output = execute(options).map_err(|err| Error::Execute(err, now.elapsed()))?;
+    let wall_seconds = options.limits.wall_seconds;
+    let result = execute(options).map_err(|err| Error::Execute(err, now.elapsed()))?;
     let elapsed = now.elapsed();
-    get_output(output, elapsed).map_err(|err| Error::Output(err, now.elapsed()))
+
+    if result.timed_out {
+        let output = ErrorOutput {
+            stdout: String::from_utf8_lossy(&result.output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&result.output.stderr).to_string(),
+            exit_code: None,
+            signal: None,
+            exceeded_limit: None,
+            limit_hint: None,
+            events: result.events,
+            truncation: result.truncation,
+            peak_memory_bytes: result.peak_memory_bytes,
+        };
+
+        return Err(Error::Timeout(
+            Box::new(output),
+            wall_seconds.unwrap_or(0),
+            elapsed,
+        ));
+    }
+
+    get_output(result, elapsed).map_err(|err| Error::Output(err, now.elapsed()))
 }
 
 #[derive(Debug)]
 pub enum Error {
     Execute(ExecuteError, Duration),
     Output(OutputError, Duration),
+    Timeout(Box<ErrorOutput>, u64, Duration),
 }
 
 impl Error {
@@ -31,6 +160,7 @@ impl Error {
         match self {
             Error::Execute(_, duration) => *duration,
             Error::Output(_, duration) => *duration,
+            Error::Timeout(_, _, duration) => *duration,
         }
     }
 }
@@ -45,6 +175,14 @@ impl fmt::Display for Error {
             Error::Output(err, _) => {
                 write!(f, "Error in output from command. {}", err)
             }
+
+            Error::Timeout(_, seconds, _) => {
+                write!(
+                    f,
+                    "Timeout, the command was killed after {} seconds",
+                    seconds
+                )
+            }
         }
     }
 }
@@ -53,6 +191,10 @@ impl fmt::Display for Error {
 pub enum ExecuteError {
     Execute(io::Error),
     CaptureStdin(),
+    CaptureStdout(),
+    CaptureStderr(),
+    InvalidChroot(),
+    Cgroup(cgroup::Error),
     WriteStdin(io::Error),
     WaitForChild(io::Error),
 }
@@ -68,6 +210,22 @@ impl fmt::Display for ExecuteError {
                 write!(f, "Failed to capture stdin.")
             }
 
+            ExecuteError::CaptureStdout() => {
+                write!(f, "Failed to capture stdout.")
+            }
+
+            ExecuteError::CaptureStderr() => {
+                write!(f, "Failed to capture stderr.")
+            }
+
+            ExecuteError::InvalidChroot() => {
+                write!(f, "Chroot directory contains a nul byte.")
+            }
+
+            ExecuteError::Cgroup(err) => {
+                write!(f, "{}", err)
+            }
+
             ExecuteError::WriteStdin(err) => {
                 write!(f, "Failed to write to stdin. {}", err)
             }
@@ -79,27 +237,728 @@ impl fmt::Display for ExecuteError {
     }
 }
 
-pub fn execute(options: Options) -> Result<process::Output, ExecuteError> {
-    let mut child = process::Command::new("sh")
+pub struct ExecuteOutput {
+    pub output: process::Output,
+    pub timed_out: bool,
+    pub events: Vec<OutputEvent>,
+    pub truncation: Truncation,
+    pub exceeded_limit: Option<ExceededLimit>,
+    pub limit_hint: Option<LimitHint>,
+    pub peak_memory_bytes: Option<u64>,
+}
+
+// The limit that made the command fail
+#[derive(Debug, Clone, Copy)]
+pub enum ExceededLimit {
+    Cpu(u64),
+    Memory(u64),
+    FileSize(u64),
+}
+
+impl fmt::Display for ExceededLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExceededLimit::Cpu(seconds) => {
+                write!(f, "CPU time limit of {} seconds exceeded", seconds)
+            }
+
+            ExceededLimit::Memory(bytes) => {
+                write!(f, "Memory limit of {} bytes exceeded", bytes)
+            }
+
+            ExceededLimit::FileSize(bytes) => {
+                write!(f, "File size limit of {} bytes exceeded", bytes)
+            }
+        }
+    }
+}
+
+// A limit that may have made the command fail, only based on the output of the command
+#[derive(Debug, Clone, Copy)]
+pub enum LimitHint {
+    Memory(u64),
+    FileSize(u64),
+}
+
+impl fmt::Display for LimitHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitHint::Memory(bytes) => {
+                write!(
+                    f,
+                    "Memory limit of {} bytes may have been exceeded, stderr mentions a failed allocation",
+                    bytes
+                )
+            }
+
+            LimitHint::FileSize(bytes) => {
+                write!(
+                    f,
+                    "File size limit of {} bytes may have been exceeded, stderr mentions a file that is too large",
+                    bytes
+                )
+            }
+        }
+    }
+}
+
+// The number of bytes written by the command to the outputs that were truncated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Truncation {
+    pub stdout_bytes: Option<usize>,
+    pub stderr_bytes: Option<usize>,
+}
+
+// A chunk of output as it was read from the pipe, elapsed is the time since the command was started
+#[derive(Debug)]
+pub struct OutputEvent {
+    pub stream: Stream,
+    pub elapsed: Duration,
+    pub content: Vec<u8>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+pub fn execute(options: Options) -> Result<ExecuteOutput, ExecuteError> {
+    let start = Instant::now();
+    let deadline = options
+        .limits
+        .wall_seconds
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+
+    let limits = options.limits;
+    let umask = options.umask;
+    let user = options.user;
+    let isolate_network = options.isolate_network;
+
+    // Removed when it goes out of scope, after the output was read
+    let cgroup = options
+        .cgroup
+        .as_ref()
+        .map(|parent| cgroup::create(parent, &limits))
+        .transpose()
+        .map_err(ExecuteError::Cgroup)?;
+
+    let cgroup_procs = cgroup
+        .as_ref()
+        .map(|cgroup| cgroup.open_procs())
+        .transpose()
+        .map_err(ExecuteError::Cgroup)?;
+
+    let cgroup_procs_fd = cgroup_procs.as_ref().map(|file| file.as_raw_fd());
+
+    let rlimits = rlimits(&limits, cgroup.is_some());
+
+    // Allocated before the fork, pre_exec can't allocate
+    let chroot = options
+        .chroot
+        .as_ref()
+        .map(|root| ffi::CString::new(root.as_os_str().as_bytes()))
+        .transpose()
+        .map_err(|_| ExecuteError::InvalidChroot())?;
+    let mut command = shell_command(&options);
+
+    // Runs in the forked child before exec, so only async-signal-safe calls are allowed
+    unsafe {
+        command.pre_exec(move || {
+            // Joined first, the cgroup may not be writable after the privileges are dropped
+            if let Some(fd) = cgroup_procs_fd {
+                join_cgroup(fd)?;
+            }
+
+            set_resource_limits(&rlimits)?;
+
+            if let Some(umask) = umask {
+                libc::umask(umask as libc::mode_t);
+            }
+
+            // The current directory is already the work path, which stays valid in the new root
+            if let Some(root) = &chroot {
+                if libc::chroot(root.as_ptr()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if isolate_network {
+                unshare_network()?;
+            }
+
+            if let Some(user) = &user {
+                drop_privileges(user)?;
+            }
+
+            start_process_group()
+        });
+    }
+
+    let mut child = command
         .arg("-c")
-        .arg(options.command)
+        .arg(&options.command)
         .current_dir(&options.work_path)
+        .envs(options.env.iter().map(|(name, value)| (name, value)))
         .stdin(process::Stdio::piped())
         .stderr(process::Stdio::piped())
         .stdout(process::Stdio::piped())
         .spawn()
         .map_err(ExecuteError::Execute)?;
 
-    if let Some(stdin) = options.stdin {
-        child
-            .stdin
-            .as_mut()
-            .ok_or(ExecuteError::CaptureStdin())?
-            .write_all(stdin.as_bytes())
+    let pid = child.id();
+    lock_running().push(pid);
+
+    let child_stdin = child.stdin.take().ok_or(ExecuteError::CaptureStdin())?;
+    let stdin = options.stdin;
+
+    // Write stdin from a separate thread so a child that fills its stdout / stderr
+    // pipes before consuming all of stdin can't deadlock us
+    let close_stdin = options.close_stdin;
+    let (exited_sender, exited_receiver) = mpsc::channel();
+    let stdin_writer =
+        thread::spawn(move || write_stdin(child_stdin, stdin, close_stdin, exited_receiver));
+
+    let max_output_bytes = options.limits.output_bytes.map(|bytes| bytes as usize);
+
+    let stdout_reader = PipeReader::start(
+        child.stdout.take().ok_or(ExecuteError::CaptureStdout())?,
+        options.output_events,
+        max_output_bytes,
+    );
+    let stderr_reader = PipeReader::start(
+        child.stderr.take().ok_or(ExecuteError::CaptureStderr())?,
+        options.output_events,
+        max_output_bytes,
+    );
+
+    let wait_result = wait_with_deadline(&mut child, deadline, options.term_grace_period);
+    lock_running().retain(|running_pid| *running_pid != pid);
+
+    // Wakes the stdin writer from the delays of a script
+    drop(exited_sender);
+
+    let (status, timed_out) = wait_result.map_err(ExecuteError::WaitForChild)?;
+
+    let output_deadline = if timed_out {
+        Some(Instant::now() + KILL_GRACE_PERIOD)
+    } else {
+        deadline
+    };
+
+    let stdout = stdout_reader.finish(output_deadline);
+    let stderr = stderr_reader.finish(output_deadline);
+
+    let truncation = Truncation {
+        stdout_bytes: stdout.truncated_size(),
+        stderr_bytes: stderr.truncated_size(),
+    };
+
+    let oom_killed = cgroup.as_ref().is_some_and(|cgroup| cgroup.oom_killed());
+
+    let exceeded_limit = match limits.memory_bytes {
+        Some(bytes) if oom_killed => Some(ExceededLimit::Memory(bytes)),
+        _ if status.success() => None,
+        _ => exceeded_limit(&limits, &rlimits, &status),
+    };
+
+    let limit_hint = match exceeded_limit {
+        None if !status.success() => limit_hint(&limits, &stderr.bytes),
+        _ => None,
+    };
+
+    let peak_memory_bytes = cgroup
+        .as_ref()
+        .and_then(|cgroup| cgroup.peak_memory_bytes());
+
+    let mut events = Vec::new();
+    events.extend(stdout.events(Stream::Stdout, start));
+    events.extend(stderr.events(Stream::Stderr, start));
+    events.sort_by_key(|event| event.elapsed);
+
+    // The writer may be blocked forever on a pipe that nobody reads if the command was killed
+    if !timed_out {
+        stdin_writer
+            .join()
+            .unwrap_or(Ok(None))
             .map_err(ExecuteError::WriteStdin)?;
     }
 
-    child.wait_with_output().map_err(ExecuteError::WaitForChild)
+    Ok(ExecuteOutput {
+        output: process::Output {
+            status,
+            stdout: stdout.bytes,
+            stderr: stderr.bytes,
+        },
+        timed_out,
+        events,
+        truncation,
+        exceeded_limit,
+        limit_hint,
+        peak_memory_bytes,
+    })
+}
+
+// The kernel sends SIGXCPU when the cpu limit is reached and SIGXFSZ when a file
+// would grow beyond the file size limit. Without a cgroup, allocations fail at the memory rlimit and the program
+// usually crashes on it or is killed by the OOM killer
+fn exceeded_limit(
+    limits: &Limits,
+    rlimits: &Limits,
+    status: &process::ExitStatus,
+) -> Option<ExceededLimit> {
+    let signal = exit_signal(status);
+
+    if let Some(seconds) = limits.cpu_seconds {
+        if signal == Some(libc::SIGXCPU) {
+            return Some(ExceededLimit::Cpu(seconds));
+        }
+    }
+
+    if let Some(bytes) = limits.fsize_bytes {
+        if signal == Some(libc::SIGXFSZ) {
+            return Some(ExceededLimit::FileSize(bytes));
+        }
+    }
+
+    let memory_bytes = rlimits.memory_bytes?;
+
+    matches!(signal, Some(libc::SIGKILL) | Some(libc::SIGSEGV))
+        .then_some(ExceededLimit::Memory(memory_bytes))
+}
+
+// Runtimes that fail to allocate memory usually exit with a message instead of a signal,
+// runtimes that ignore SIGXFSZ get EFBIG from the write instead
+fn limit_hint(limits: &Limits, stderr: &[u8]) -> Option<LimitHint> {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+
+    if let Some(bytes) = limits.fsize_bytes {
+        if stderr.contains(FILE_TOO_LARGE_MESSAGE) {
+            return Some(LimitHint::FileSize(bytes));
+        }
+    }
+
+    let memory_bytes = limits.memory_bytes?;
+
+    OUT_OF_MEMORY_MESSAGES
+        .iter()
+        .any(|message| stderr.contains(message))
+        .then_some(LimitHint::Memory(memory_bytes))
+}
+
+// The signal that killed the command, the shell exits with 128 + the signal
+// if the command was not exec'd by the shell
+fn exit_signal(status: &process::ExitStatus) -> Option<i32> {
+    status.signal().or_else(|| {
+        status
+            .code()
+            .filter(|code| *code > 128)
+            .map(|code| code - 128)
+    })
+}
+
+// Send the signal to the process groups of all running commands,
+// returns the number of commands signaled
+pub fn signal_running(signal: libc::c_int) -> usize {
+    let running = lock_running();
+
+    running
+        .iter()
+        .filter(|pid| unsafe { libc::killpg(**pid as libc::pid_t, signal) } == 0)
+        .count()
+}
+
+// SIGTERM the running commands and SIGKILL the ones that are still running after the grace period
+pub fn terminate_running(grace_period: Duration) {
+    if signal_running(libc::SIGTERM) == 0 {
+        return;
+    }
+
+    let kill_at = Instant::now() + grace_period;
+
+    while Instant::now() < kill_at && !lock_running().is_empty() {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    signal_running(libc::SIGKILL);
+}
+
+fn lock_running() -> MutexGuard<'static, Vec<u32>> {
+    RUNNING.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn shell_command(options: &Options) -> process::Command {
+    if options.sandbox {
+        sandboxed_shell(&options.work_path)
+    } else {
+        process::Command::new("sh")
+    }
+}
+
+// Run sh with sandbox-exec, file writes are only allowed in the work path
+// and temp directories, network access is denied
+#[cfg(target_os = "macos")]
+fn sandboxed_shell(work_path: &path::Path) -> process::Command {
+    // The profile matches on resolved paths, i.e. /tmp is /private/tmp
+    let work_path = work_path
+        .canonicalize()
+        .unwrap_or_else(|_| work_path.to_path_buf());
+
+    let profile = SANDBOX_PROFILE.replace(
+        "%s",
+        &work_path
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\""),
+    );
+
+    let mut command = process::Command::new("sandbox-exec");
+    command.arg("-p").arg(profile).arg("sh");
+    command
+}
+
+// Sandboxing is only supported on macOS, the config rejects --sandbox on other platforms
+#[cfg(not(target_os = "macos"))]
+fn sandboxed_shell(_work_path: &path::Path) -> process::Command {
+    process::Command::new("sh")
+}
+
+// Wait for the child to exit, its process group is terminated if it's still running at the deadline
+fn wait_with_deadline(
+    child: &mut process::Child,
+    deadline: Option<Instant>,
+    grace_period: Duration,
+) -> io::Result<(process::ExitStatus, bool)> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            terminate_process_group(child, grace_period)?;
+            return Ok((child.wait()?, true));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// SIGTERM the command and the processes it started, so programs can flush their output
+// and clean up, then SIGKILL whatever is left after the grace period, so compilers and
+// language VMs don't keep running in the container. The child is the leader of the process group
+fn terminate_process_group(child: &mut process::Child, grace_period: Duration) -> io::Result<()> {
+    let pgid = child.id() as libc::pid_t;
+
+    if !grace_period.is_zero() && unsafe { libc::killpg(pgid, libc::SIGTERM) } == 0 {
+        let kill_at = Instant::now() + grace_period;
+
+        while Instant::now() < kill_at && child.try_wait()?.is_none() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Also kills processes of the group that outlived the child
+    if unsafe { libc::killpg(pgid, libc::SIGKILL) } != 0 {
+        // Fails if the group is gone, the child is reaped by the caller either way
+        let _ = child.kill();
+    }
+
+    Ok(())
+}
+
+// Reads a pipe from a separate thread, so the output that was read before a timeout is kept
+struct PipeReader {
+    buffer: Arc<Mutex<PipeOutput>>,
+    done: mpsc::Receiver<()>,
+}
+
+#[derive(Default)]
+struct PipeOutput {
+    bytes: Vec<u8>,
+    // Number of bytes read from the pipe, including the ones that were not kept
+    size: usize,
+    // When each chunk was read and the length of the output after it
+    chunks: Vec<(Instant, usize)>,
+}
+
+impl PipeOutput {
+    fn truncated_size(&self) -> Option<usize> {
+        if self.size > self.bytes.len() {
+            Some(self.size)
+        } else {
+            None
+        }
+    }
+
+    fn events(&self, stream: Stream, start: Instant) -> Vec<OutputEvent> {
+        let mut chunk_start = 0;
+
+        self.chunks
+            .iter()
+            .map(|(read_at, chunk_end)| {
+                let content = self.bytes[chunk_start..*chunk_end].to_vec();
+                chunk_start = *chunk_end;
+
+                OutputEvent {
+                    stream,
+                    elapsed: read_at.saturating_duration_since(start),
+                    content,
+                }
+            })
+            .collect()
+    }
+}
+
+impl PipeReader {
+    // Output beyond max_bytes is read and counted, but not kept
+    fn start<R: Read + Send + 'static>(
+        mut pipe: R,
+        record_chunks: bool,
+        max_bytes: Option<usize>,
+    ) -> PipeReader {
+        let buffer = Arc::new(Mutex::new(PipeOutput::default()));
+        let (sender, done) = mpsc::channel();
+        let thread_buffer = buffer.clone();
+
+        thread::spawn(move || {
+            let mut chunk = [0u8; 8192];
+
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let mut output = lock(&thread_buffer);
+                        output.size += n;
+
+                        let remaining = max_bytes
+                            .map(|max_bytes| max_bytes.saturating_sub(output.bytes.len()))
+                            .unwrap_or(n);
+                        let keep = n.min(remaining);
+
+                        if keep == 0 {
+                            continue;
+                        }
+
+                        output.bytes.extend_from_slice(&chunk[..keep]);
+
+                        if record_chunks {
+                            let length = output.bytes.len();
+                            output.chunks.push((Instant::now(), length));
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+
+            let _ = sender.send(());
+        });
+
+        PipeReader { buffer, done }
+    }
+
+    // Wait until the pipe is closed, or at most until the deadline
+    fn finish(self, deadline: Option<Instant>) -> PipeOutput {
+        let _ = match deadline {
+            Some(deadline) => self
+                .done
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+
+            None => self.done.recv().ok(),
+        };
+
+        let mut output = std::mem::take(&mut *lock(&self.buffer));
+
+        if output.truncated_size().is_some() {
+            truncate_partial_char(&mut output.bytes);
+        }
+
+        output
+    }
+}
+
+// Remove the incomplete utf-8 sequence the output may end with after it was truncated
+fn truncate_partial_char(bytes: &mut Vec<u8>) {
+    if let Err(err) = std::str::from_utf8(bytes) {
+        if err.error_len().is_none() {
+            bytes.truncate(err.valid_up_to());
+        }
+    }
+}
+
+fn lock(buffer: &Mutex<PipeOutput>) -> MutexGuard<'_, PipeOutput> {
+    buffer.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// The command gets its own process group, so a signal reaches the program
+// and not only the shell that started it. SIGINT is ignored in background jobs
+// of a shell, and the ignore would be inherited by the command
+fn start_process_group() -> io::Result<()> {
+    if unsafe { libc::setpgid(0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Writing 0 to cgroup.procs moves the writing process, the cgroup is inherited by its children
+fn join_cgroup(procs_fd: libc::c_int) -> io::Result<()> {
+    let pid = b"0";
+
+    if unsafe { libc::write(procs_fd, pid.as_ptr() as *const libc::c_void, pid.len()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// A new network namespace only has a loopback interface, which is down.
+// It's brought up so programs can still connect to themselves over localhost
+#[cfg(target_os = "linux")]
+fn unshare_network() -> io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+
+    for (target, byte) in request.ifr_name.iter_mut().zip(b"lo") {
+        *target = *byte as libc::c_char;
+    }
+
+    let result = unsafe {
+        if libc::ioctl(socket, libc::SIOCGIFFLAGS as _, &mut request) == 0 {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            libc::ioctl(socket, libc::SIOCSIFFLAGS as _, &request)
+        } else {
+            -1
+        }
+    };
+
+    // The error has to be taken before close can overwrite it
+    let error = io::Error::last_os_error();
+    unsafe { libc::close(socket) };
+
+    if result != 0 {
+        return Err(error);
+    }
+
+    Ok(())
+}
+
+// Network namespaces are only available on Linux, the config rejects --no-network on other platforms
+#[cfg(not(target_os = "linux"))]
+fn unshare_network() -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+// Switch to the user after the limits are set and the root is changed, which needs
+// privileges. The group is set first and the supplementary groups of the runner
+// are dropped, they would still give access to its files
+fn drop_privileges(user: &User) -> io::Result<()> {
+    if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::setgid(user.gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if unsafe { libc::setuid(user.uid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// The limits that are set with setrlimit. In a cgroup the memory limit is only enforced
+// by memory.max, a virtual memory limit would keep the JVM, .NET, V8 and BEAM from starting
+fn rlimits(limits: &Limits, in_cgroup: bool) -> Limits {
+    Limits {
+        memory_bytes: limits.memory_bytes.filter(|_| !in_cgroup),
+        ..*limits
+    }
+}
+
+// The limits are inherited by all processes started by the command,
+// the wall time limit is enforced by `execute`
+fn set_resource_limits(limits: &Limits) -> io::Result<()> {
+    // The soft cpu limit sends SIGXCPU, the hard limit a second later sends SIGKILL
+    let resource_limits = [
+        (
+            libc::RLIMIT_CPU,
+            limits.cpu_seconds,
+            limits.cpu_seconds.map(|seconds| seconds + 1),
+        ),
+        (libc::RLIMIT_AS, limits.memory_bytes, limits.memory_bytes),
+        (libc::RLIMIT_DATA, limits.memory_bytes, limits.memory_bytes),
+        (libc::RLIMIT_FSIZE, limits.fsize_bytes, limits.fsize_bytes),
+        (libc::RLIMIT_NOFILE, limits.nofile, limits.nofile),
+        (libc::RLIMIT_NPROC, limits.nproc, limits.nproc),
+    ];
+
+    for (resource, soft, hard) in resource_limits {
+        if let (Some(soft), Some(hard)) = (soft, hard) {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the pipe if it should be kept open. The delays of a script end early when
+// the child exited, the remaining steps are not written then
+fn write_stdin(
+    mut child_stdin: process::ChildStdin,
+    stdin: Option<Stdin>,
+    close_stdin: bool,
+    exited: mpsc::Receiver<()>,
+) -> io::Result<Option<process::ChildStdin>> {
+    let result = match stdin {
+        Some(Stdin::Text(text)) => child_stdin.write_all(text.as_bytes()),
+
+        Some(Stdin::Script(steps)) => steps.iter().try_for_each(|step| {
+            match exited.recv_timeout(Duration::from_millis(step.delay_ms)) {
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                _ => return Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+            }
+
+            child_stdin.write_all(step.data.as_bytes())?;
+            child_stdin.flush()
+        }),
+
+        None => Ok(()),
+    };
+
+    match result {
+        // The child exited or closed stdin without reading all of it
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(None),
+
+        Err(err) => Err(err),
+
+        Ok(()) if close_stdin => Ok(None),
+
+        Ok(()) => Ok(Some(child_stdin)),
+    }
 }
 
 #[derive(Debug)]
@@ -107,6 +966,9 @@ pub struct SuccessOutput {
     pub stdout: String,
     pub stderr: String,
     pub duration: Duration,
+    pub events: Vec<OutputEvent>,
+    pub truncation: Truncation,
+    pub peak_memory_bytes: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -114,6 +976,13 @@ pub struct ErrorOutput {
     pub stdout: String,
     pub stderr: String,
     pub exit_code: Option<i32>,
+    // Also set when the shell reported the signal as 128 + the signal
+    pub signal: Option<i32>,
+    pub exceeded_limit: Option<ExceededLimit>,
+    pub limit_hint: Option<LimitHint>,
+    pub events: Vec<OutputEvent>,
+    pub truncation: Truncation,
+    pub peak_memory_bytes: Option<u64>,
 }
 
 impl fmt::Display for ErrorOutput {
@@ -138,7 +1007,7 @@ impl fmt::Display for ErrorOutput {
 
 #[derive(Debug)]
 pub enum OutputError {
-    ExitFailure(ErrorOutput),
+    ExitFailure(Box<ErrorOutput>),
     ReadStdout(string::FromUtf8Error),
     ReadStderr(string::FromUtf8Error),
 }
@@ -161,10 +1030,9 @@ impl fmt::Display for OutputError {
     }
 }
 
-pub fn get_output(
-    output: process::Output,
-    duration: Duration,
-) -> Result<SuccessOutput, OutputError> {
+pub fn get_output(result: ExecuteOutput, duration: Duration) -> Result<SuccessOutput, OutputError> {
+    let output = result.output;
+
     if output.status.success() {
         let stdout = String::from_utf8(output.stdout).map_err(OutputError::ReadStdout)?;
 
@@ -174,6 +1042,9 @@ pub fn get_output(
             stdout,
             stderr,
             duration,
+            events: result.events,
+            truncation: result.truncation,
+            peak_memory_bytes: result.peak_memory_bytes,
         })
     } else {
         let stdout = String::from_utf8(output.stdout).map_err(OutputError::ReadStdout)?;
@@ -182,10 +1053,110 @@ pub fn get_output(
 
         let exit_code = output.status.code();
 
-        Err(OutputError::ExitFailure(ErrorOutput {
+        let signal = exit_signal(&output.status);
+
+        Err(OutputError::ExitFailure(Box::new(ErrorOutput {
             stdout,
             stderr,
             exit_code,
-        }))
+            signal,
+            exceeded_limit: result.exceeded_limit,
+            limit_hint: result.limit_hint,
+            events: result.events,
+            truncation: result.truncation,
+            peak_memory_bytes: result.peak_memory_bytes,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_rlimit_without_cgroup() {
+        let limits = Limits {
+            memory_bytes: Some(1024),
+            nofile: Some(64),
+            ..Limits::default()
+        };
+
+        let rlimits = rlimits(&limits, false);
+
+        assert_eq!(rlimits.memory_bytes, Some(1024));
+        assert_eq!(rlimits.nofile, Some(64));
+    }
+
+    #[test]
+    fn no_memory_rlimit_in_cgroup() {
+        let limits = Limits {
+            memory_bytes: Some(1024),
+            nofile: Some(64),
+            ..Limits::default()
+        };
+
+        let rlimits = rlimits(&limits, true);
+
+        assert_eq!(rlimits.memory_bytes, None);
+        assert_eq!(rlimits.nofile, Some(64));
+    }
+
+    #[test]
+    fn memory_exceeded_from_signal() {
+        let limits = Limits {
+            memory_bytes: Some(1024),
+            ..Limits::default()
+        };
+
+        let status = process::ExitStatus::from_raw(libc::SIGSEGV);
+
+        assert!(matches!(
+            exceeded_limit(&limits, &rlimits(&limits, false), &status),
+            Some(ExceededLimit::Memory(1024))
+        ));
+        assert!(exceeded_limit(&limits, &rlimits(&limits, true), &status).is_none());
+    }
+
+    #[test]
+    fn memory_message_is_only_a_hint() {
+        let limits = Limits {
+            memory_bytes: Some(1024),
+            ..Limits::default()
+        };
+
+        let status = process::ExitStatus::from_raw(1 << 8);
+        let stderr = b"MemoryError: out of memory";
+
+        assert!(exceeded_limit(&limits, &rlimits(&limits, false), &status).is_none());
+        assert!(matches!(
+            limit_hint(&limits, stderr),
+            Some(LimitHint::Memory(1024))
+        ));
+        assert!(limit_hint(&Limits::default(), stderr).is_none());
+    }
+
+    #[test]
+    fn file_size_message_is_only_a_hint() {
+        let limits = Limits {
+            fsize_bytes: Some(1024),
+            ..Limits::default()
+        };
+
+        let status = process::ExitStatus::from_raw(1 << 8);
+        let stderr = b"write: File too large";
+
+        assert!(exceeded_limit(&limits, &limits, &status).is_none());
+        assert!(matches!(
+            limit_hint(&limits, stderr),
+            Some(LimitHint::FileSize(1024))
+        ));
+        assert!(matches!(
+            exceeded_limit(
+                &limits,
+                &limits,
+                &process::ExitStatus::from_raw(libc::SIGXFSZ)
+            ),
+            Some(ExceededLimit::FileSize(1024))
+        ));
     }
 }
@@ -0,0 +1,354 @@
+use crate::cmd;
+use crate::executable;
+use crate::language::Language;
+use crate::limits::Limits;
+use std::collections::HashMap;
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::num;
+use std::path;
+use std::time;
+
+const DEFAULT_MAX_STDIN_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 1000;
+const DEFAULT_MAX_DIRECTORY_DEPTH: usize = 20;
+const DEFAULT_TERM_GRACE_PERIOD: time::Duration = time::Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct Config {
+    pub work_path: Option<path::PathBuf>,
+    pub max_stdin_bytes: usize,
+    pub max_files: usize,
+    pub max_directory_depth: usize,
+    pub language_limits: HashMap<Language, Limits>,
+    pub compile_daemon: bool,
+    pub sandbox: bool,
+    pub keep_workdir: bool,
+    pub teardown_commands: Vec<String>,
+    pub faketime_library: Option<path::PathBuf>,
+    pub chroot: Option<path::PathBuf>,
+    pub bootstrap_cache: Option<path::PathBuf>,
+    // How long commands get to exit after SIGTERM before they are killed
+    pub term_grace_period: time::Duration,
+    pub run_as_user: Option<cmd::User>,
+    // Parent of the cgroups that are created for the commands
+    pub cgroup: Option<path::PathBuf>,
+    pub no_network: bool,
+    pub validate: bool,
+    pub kernel: Option<KernelConfig>,
+}
+
+// Set by the `kernel` subcommand
+#[derive(Debug, Clone)]
+pub struct KernelConfig {
+    pub connection_file: path::PathBuf,
+    pub language: String,
+    pub file_name: String,
+    pub term_grace_period: time::Duration,
+}
+
+pub fn from_args(arguments: Vec<String>) -> Result<Config, Error> {
+    let mut config = Config {
+        work_path: None,
+        max_stdin_bytes: DEFAULT_MAX_STDIN_BYTES,
+        max_files: DEFAULT_MAX_FILES,
+        max_directory_depth: DEFAULT_MAX_DIRECTORY_DEPTH,
+        language_limits: HashMap::new(),
+        compile_daemon: false,
+        sandbox: false,
+        keep_workdir: false,
+        teardown_commands: vec![],
+        faketime_library: None,
+        chroot: None,
+        bootstrap_cache: None,
+        term_grace_period: DEFAULT_TERM_GRACE_PERIOD,
+        run_as_user: None,
+        cgroup: None,
+        no_network: false,
+        validate: false,
+        kernel: None,
+    };
+
+    let mut kernel = false;
+    let mut connection_file = None;
+    let mut kernel_language = None;
+    let mut file_name = None;
+
+    let mut args = arguments.into_iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => {
+                let value = next_value(&mut args, &arg)?;
+                config.work_path = Some(path::PathBuf::from(value));
+            }
+
+            "--max-stdin-bytes" => {
+                let value = next_value(&mut args, &arg)?;
+                config.max_stdin_bytes = parse_number(&arg, &value)?;
+            }
+
+            "--max-files" => {
+                let value = next_value(&mut args, &arg)?;
+                config.max_files = parse_number(&arg, &value)?;
+            }
+
+            "--max-directory-depth" => {
+                let value = next_value(&mut args, &arg)?;
+                config.max_directory_depth = parse_number(&arg, &value)?;
+            }
+
+            "--limits-file" => {
+                let value = next_value(&mut args, &arg)?;
+                config.language_limits = read_limits_file(path::Path::new(&value))?;
+            }
+
+            // Only Scala 2.12 and older have a compile server, fsc was removed in Scala 2.13.
+            // Scala 3 and Kotlin have none that can be used from the command line
+            "--compile-daemon" if executable::find("fsc").is_some() => {
+                config.compile_daemon = true;
+            }
+
+            "--compile-daemon" => {
+                return Err(Error::UnsupportedArgument(
+                    arg,
+                    "the scala 2.12 compile server (fsc) is not installed".to_string(),
+                ))
+            }
+
+            "--sandbox" if cfg!(target_os = "macos") => {
+                config.sandbox = true;
+            }
+
+            "--sandbox" => {
+                return Err(Error::UnsupportedArgument(
+                    arg,
+                    "sandbox-exec is only available on macOS".to_string(),
+                ))
+            }
+
+            "--no-network" if cfg!(target_os = "linux") => {
+                config.no_network = true;
+            }
+
+            "--no-network" => {
+                return Err(Error::UnsupportedArgument(
+                    arg,
+                    "network namespaces are only available on Linux".to_string(),
+                ))
+            }
+
+            "--teardown-command" => {
+                let value = next_value(&mut args, &arg)?;
+                config.teardown_commands.push(value);
+            }
+
+            "--faketime-library" => {
+                let value = next_value(&mut args, &arg)?;
+                config.faketime_library = Some(path::PathBuf::from(value));
+            }
+
+            "--chroot" => {
+                let value = next_value(&mut args, &arg)?;
+                config.chroot = Some(path::PathBuf::from(value));
+            }
+
+            "--bootstrap-cache" => {
+                let value = next_value(&mut args, &arg)?;
+                config.bootstrap_cache = Some(path::PathBuf::from(value));
+            }
+
+            "--term-grace-ms" => {
+                let value = next_value(&mut args, &arg)?;
+                config.term_grace_period =
+                    time::Duration::from_millis(parse_number(&arg, &value)? as u64);
+            }
+
+            "--run-as-user" => {
+                let value = next_value(&mut args, &arg)?;
+                config.run_as_user = Some(parse_user(&arg, &value)?);
+            }
+
+            "--cgroup" => {
+                let value = next_value(&mut args, &arg)?;
+                config.cgroup = Some(path::PathBuf::from(value));
+            }
+
+            "--validate" => {
+                config.validate = true;
+            }
+
+            "--keep-workdir" => {
+                config.keep_workdir = true;
+            }
+
+            "kernel" => {
+                kernel = true;
+            }
+
+            "--connection-file" => {
+                let value = next_value(&mut args, &arg)?;
+                connection_file = Some(path::PathBuf::from(value));
+            }
+
+            "--language" => {
+                let value = next_value(&mut args, &arg)?;
+                parse_language(&value)?;
+                kernel_language = Some(value);
+            }
+
+            "--file-name" => {
+                file_name = Some(next_value(&mut args, &arg)?);
+            }
+
+            _ => return Err(Error::UnknownArgument(arg)),
+        }
+    }
+
+    if kernel {
+        config.kernel = Some(KernelConfig {
+            connection_file: connection_file
+                .ok_or_else(|| Error::MissingArgument("--connection-file".to_string()))?,
+            language: kernel_language
+                .ok_or_else(|| Error::MissingArgument("--language".to_string()))?,
+            file_name: file_name
+                .ok_or_else(|| Error::MissingArgument("--file-name".to_string()))?,
+            term_grace_period: config.term_grace_period,
+        });
+    }
+
+    Ok(config)
+}
+
+fn next_value<I: Iterator<Item = String>>(args: &mut I, name: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::MissingValue(name.to_string()))
+}
+
+// The limits file is a json object with languages as keys, i.e.
+// {"java": {"wallSeconds": 60, "memoryBytes": null}}
+fn read_limits_file(file_path: &path::Path) -> Result<HashMap<Language, Limits>, Error> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|err| Error::ReadLimitsFile(file_path.to_path_buf(), err))?;
+
+    serde_json::from_str(&content)
+        .map_err(|err| Error::ParseLimitsFile(file_path.to_path_buf(), err))
+}
+
+fn parse_language(value: &str) -> Result<Language, Error> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| Error::UnknownLanguage(value.to_string()))
+}
+
+// A user name or uid, optionally followed by `:<gid>`.
+// Without a gid the primary group of the user is used
+fn parse_user(name: &str, value: &str) -> Result<cmd::User, Error> {
+    let (user, group) = match value.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (value, None),
+    };
+
+    let passwd = match user.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+
+        Err(_) => {
+            let user_name =
+                ffi::CString::new(user).map_err(|_| Error::UnknownUser(user.to_string()))?;
+            unsafe { libc::getpwnam(user_name.as_ptr()) }
+        }
+    };
+
+    // The pointer is only valid until the next lookup, the ids are copied right away
+    let entry = unsafe { passwd.as_ref() }.map(|passwd| (passwd.pw_uid, passwd.pw_gid));
+
+    // Numeric ids don't need a passwd entry if the gid is given
+    let uid = match (entry, user.parse::<libc::uid_t>()) {
+        (Some((uid, _)), _) => uid,
+        (None, Ok(uid)) => uid,
+        (None, Err(_)) => return Err(Error::UnknownUser(user.to_string())),
+    };
+
+    let gid = match (group, entry) {
+        (Some(group), _) => group
+            .parse()
+            .map_err(|err| Error::InvalidNumber(name.to_string(), err))?,
+
+        (None, Some((_, gid))) => gid,
+
+        (None, None) => return Err(Error::UnknownUser(user.to_string())),
+    };
+
+    Ok(cmd::User { uid, gid })
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize, Error> {
+    value
+        .parse()
+        .map_err(|err| Error::InvalidNumber(name.to_string(), err))
+}
+
+pub enum Error {
+    UnknownArgument(String),
+    UnsupportedArgument(String, String),
+    MissingValue(String),
+    MissingArgument(String),
+    UnknownLanguage(String),
+    UnknownUser(String),
+    InvalidNumber(String, num::ParseIntError),
+    ReadLimitsFile(path::PathBuf, io::Error),
+    ParseLimitsFile(path::PathBuf, serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownArgument(arg) => {
+                write!(f, "Unknown argument: '{}'", arg)
+            }
+
+            Error::UnsupportedArgument(arg, reason) => {
+                write!(f, "Unsupported argument: '{}', {}", arg, reason)
+            }
+
+            Error::MissingValue(name) => {
+                write!(f, "Missing value for argument: '{}'", name)
+            }
+
+            Error::MissingArgument(name) => {
+                write!(f, "Missing required argument: '{}'", name)
+            }
+
+            Error::UnknownLanguage(language) => {
+                write!(f, "Unknown language: '{}'", language)
+            }
+
+            Error::UnknownUser(user) => {
+                write!(f, "Unknown user: '{}'", user)
+            }
+
+            Error::InvalidNumber(name, err) => {
+                write!(f, "Invalid number for argument '{}'. {}", name, err)
+            }
+
+            Error::ReadLimitsFile(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to read limits file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::ParseLimitsFile(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to parse limits file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path;
+
+// Find an executable with the given name in PATH
+pub fn find(name: &str) -> Option<path::PathBuf> {
+    path_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|file_path| is_executable(file_path))
+}
+
+// List names of all executables in PATH
+pub fn list_names() -> Vec<String> {
+    path_dirs()
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+        .filter(|entry| is_executable(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect()
+}
+
+fn path_dirs() -> Vec<path::PathBuf> {
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+fn is_executable(file_path: &path::Path) -> bool {
+    fs::metadata(file_path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
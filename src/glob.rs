@@ -0,0 +1,135 @@
+// Match a path against a glob pattern.
+// `*` matches any sequence of characters except `/`,
+// `?` matches a single character except `/`,
+// `**/` matches zero or more directories and
+// `[abc]`, `[a-z]` and `[!abc]` matches a single character in (or not in) the set
+pub fn is_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let path = path.chars().collect::<Vec<char>>();
+
+    let mut matcher = Matcher {
+        results: vec![None; (pattern.len() + 1) * (path.len() + 1)],
+        path_len: path.len(),
+    };
+
+    matcher.matches(&pattern, &path)
+}
+
+// The pattern and the path come from the request. Each `*` tries every split of the path,
+// so the results are remembered per remaining pattern and path, otherwise the matching
+// would take exponential time in the number of `*`
+struct Matcher {
+    results: Vec<Option<bool>>,
+    path_len: usize,
+}
+
+impl Matcher {
+    fn matches(&mut self, pattern: &[char], path: &[char]) -> bool {
+        let key = pattern.len() * (self.path_len + 1) + path.len();
+
+        if let Some(result) = self.results[key] {
+            return result;
+        }
+
+        let result = self.match_first(pattern, path);
+        self.results[key] = Some(result);
+        result
+    }
+
+    fn match_first(&mut self, pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+
+            ['*', '*', '/', pattern_rest @ ..] => {
+                // Try matching the rest of the pattern at every directory boundary
+                self.matches(pattern_rest, path)
+                    || path
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, c)| **c == '/')
+                        .any(|(index, _)| self.matches(pattern_rest, &path[index + 1..]))
+            }
+
+            ['*', '*'] => true,
+
+            ['*', pattern_rest @ ..] => {
+                let max_len = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+
+                (0..=max_len).any(|len| self.matches(pattern_rest, &path[len..]))
+            }
+
+            ['?', pattern_rest @ ..] => match path.split_first() {
+                Some((c, path_rest)) if *c != '/' => self.matches(pattern_rest, path_rest),
+
+                _ => false,
+            },
+
+            ['[', pattern_rest @ ..] => match (parse_class(pattern_rest), path.split_first()) {
+                (Some((class, pattern_rest)), Some((c, path_rest))) => {
+                    *c != '/' && class.contains(*c) && self.matches(pattern_rest, path_rest)
+                }
+
+                // Treat an unterminated class as a literal '['
+                (None, Some(('[', path_rest))) => self.matches(pattern_rest, path_rest),
+
+                _ => false,
+            },
+
+            [p, pattern_rest @ ..] => match path.split_first() {
+                Some((c, path_rest)) if c == p => self.matches(pattern_rest, path_rest),
+
+                _ => false,
+            },
+        }
+    }
+}
+
+struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn contains(&self, c: char) -> bool {
+        let in_ranges = self
+            .ranges
+            .iter()
+            .any(|(start, end)| *start <= c && c <= *end);
+
+        in_ranges != self.negated
+    }
+}
+
+// Parse a character class, the opening '[' is expected to be consumed already.
+// Returns the class and the remaining pattern after the closing ']'
+fn parse_class(pattern: &[char]) -> Option<(CharClass, &[char])> {
+    let (negated, mut rest) = match pattern {
+        ['!', rest @ ..] | ['^', rest @ ..] => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut ranges = Vec::new();
+    let mut is_first = true;
+
+    loop {
+        match rest {
+            [']', after @ ..] if !is_first => {
+                return Some((CharClass { negated, ranges }, after));
+            }
+
+            [start, '-', end, after @ ..] if *end != ']' => {
+                ranges.push((*start, *end));
+                rest = after;
+            }
+
+            [c, after @ ..] => {
+                ranges.push((*c, *c));
+                rest = after;
+            }
+
+            [] => return None,
+        }
+
+        is_first = false;
+    }
+}
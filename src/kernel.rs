@@ -0,0 +1,535 @@
+// Jupyter kernel that runs the code of each execute request as a run request.
+// See https://jupyter-client.readthedocs.io/en/stable/messaging.html
+use crate::cmd;
+use crate::config::KernelConfig;
+use crate::zmtp;
+use hmac::Hmac;
+use hmac::Mac;
+use serde_json::json;
+use serde_json::Value;
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net;
+use std::path;
+use std::process;
+use std::sync::atomic;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+const PROTOCOL_VERSION: &str = "5.3";
+
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub error: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ConnectionInfo {
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    key: String,
+}
+
+struct Kernel {
+    language: String,
+    file_extension: String,
+    key: Vec<u8>,
+    session: String,
+    message_count: atomic::AtomicU64,
+    // Also serializes the executions
+    execution_count: Mutex<u64>,
+    subscribers: Mutex<Vec<net::TcpStream>>,
+    term_grace_period: time::Duration,
+    execute: Box<dyn Fn(&str) -> Output + Send + Sync>,
+}
+
+struct Message {
+    identities: Vec<Vec<u8>>,
+    header: Value,
+    content: Value,
+}
+
+pub fn start<F>(kernel_config: &KernelConfig, execute: F) -> Result<(), Error>
+where
+    F: Fn(&str) -> Output + Send + Sync + 'static,
+{
+    let connection_info = read_connection_file(&kernel_config.connection_file)?;
+
+    if connection_info.transport != "tcp" {
+        return Err(Error::UnsupportedTransport(connection_info.transport));
+    }
+
+    let bind = |port: u16| {
+        let address = format!("{}:{}", connection_info.ip, port);
+        net::TcpListener::bind(&address).map_err(|err| Error::Bind(address, err))
+    };
+
+    let shell_listener = bind(connection_info.shell_port)?;
+    let iopub_listener = bind(connection_info.iopub_port)?;
+    let stdin_listener = bind(connection_info.stdin_port)?;
+    let control_listener = bind(connection_info.control_port)?;
+    let hb_listener = bind(connection_info.hb_port)?;
+
+    let kernel = Arc::new(Kernel {
+        language: kernel_config.language.clone(),
+        file_extension: path::Path::new(&kernel_config.file_name)
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default(),
+        key: connection_info.key.into_bytes(),
+        session: new_session_id(),
+        message_count: atomic::AtomicU64::new(0),
+        execution_count: Mutex::new(0),
+        subscribers: Mutex::new(vec![]),
+        term_grace_period: kernel_config.term_grace_period,
+        execute: Box::new(execute),
+    });
+
+    let threads = vec![
+        serve(shell_listener, "ROUTER", kernel.clone(), handle_requests),
+        serve(control_listener, "ROUTER", kernel.clone(), handle_requests),
+        serve(iopub_listener, "PUB", kernel.clone(), add_subscriber),
+        // Input requests are not supported, the connection is only kept open
+        serve(stdin_listener, "ROUTER", kernel.clone(), discard_messages),
+        serve(hb_listener, "REP", kernel, echo_messages),
+    ];
+
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    Ok(())
+}
+
+fn read_connection_file(file_path: &path::Path) -> Result<ConnectionInfo, Error> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|err| Error::ReadConnectionFile(file_path.to_path_buf(), err))?;
+
+    serde_json::from_str(&content)
+        .map_err(|err| Error::ParseConnectionFile(file_path.to_path_buf(), err))
+}
+
+// Accept connections on the listener, each connection is handled in its own thread.
+// A connection is dropped on any io error
+fn serve(
+    listener: net::TcpListener,
+    socket_type: &'static str,
+    kernel: Arc<Kernel>,
+    handler: fn(&Kernel, net::TcpStream) -> io::Result<()>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let kernel = kernel.clone();
+
+            thread::spawn(move || {
+                let mut stream = stream;
+                let _ = zmtp::handshake(&mut stream, socket_type)
+                    .and_then(|()| handler(&kernel, stream));
+            });
+        }
+    })
+}
+
+fn handle_requests(kernel: &Kernel, mut stream: net::TcpStream) -> io::Result<()> {
+    loop {
+        let frames = zmtp::read_message(&mut stream)?;
+
+        if let Some(message) = kernel.parse_message(frames) {
+            kernel.handle_request(&mut stream, &message)?;
+        }
+    }
+}
+
+fn add_subscriber(kernel: &Kernel, stream: net::TcpStream) -> io::Result<()> {
+    let reader = stream.try_clone()?;
+    kernel
+        .subscribers
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(stream);
+
+    // Subscriptions are ignored, all messages are published to all subscribers
+    discard_messages(kernel, reader)
+}
+
+fn discard_messages(_kernel: &Kernel, mut stream: net::TcpStream) -> io::Result<()> {
+    loop {
+        zmtp::read_message(&mut stream)?;
+    }
+}
+
+fn echo_messages(_kernel: &Kernel, mut stream: net::TcpStream) -> io::Result<()> {
+    loop {
+        let frames = zmtp::read_message(&mut stream)?;
+        zmtp::write_message(&mut stream, &frames)?;
+    }
+}
+
+impl Kernel {
+    fn handle_request(&self, stream: &mut net::TcpStream, request: &Message) -> io::Result<()> {
+        let msg_type = request.header["msg_type"].as_str().unwrap_or("");
+
+        self.publish_status(&request.header, "busy");
+
+        let reply_content = match msg_type {
+            "kernel_info_request" => Some(self.kernel_info()),
+
+            "execute_request" => {
+                let code = request.content["code"].as_str().unwrap_or("");
+                Some(self.execute_code(&request.header, code))
+            }
+
+            "is_complete_request" => Some(json!({"status": "unknown"})),
+
+            "complete_request" => {
+                let cursor_pos = request.content["cursor_pos"].clone();
+
+                Some(json!({
+                    "status": "ok",
+                    "matches": [],
+                    "cursor_start": cursor_pos,
+                    "cursor_end": cursor_pos,
+                    "metadata": {}
+                }))
+            }
+
+            "inspect_request" => Some(json!({
+                "status": "ok",
+                "found": false,
+                "data": {},
+                "metadata": {}
+            })),
+
+            "history_request" => Some(json!({"status": "ok", "history": []})),
+
+            "comm_info_request" => Some(json!({"status": "ok", "comms": {}})),
+
+            // Sent on the control channel when the kernel spec has `"interrupt_mode": "message"`.
+            // The signal can be given in the content, it's SIGINT by default.
+            // SIGTERM is followed by SIGKILL if the command is still running after the grace period
+            "interrupt_request" => {
+                match request.content["signal"].as_str() {
+                    Some("SIGTERM") => cmd::terminate_running(self.term_grace_period),
+                    Some("SIGKILL") => {
+                        cmd::signal_running(libc::SIGKILL);
+                    }
+                    _ => {
+                        cmd::signal_running(libc::SIGINT);
+                    }
+                }
+
+                Some(json!({"status": "ok"}))
+            }
+
+            "shutdown_request" => Some(json!({
+                "status": "ok",
+                "restart": request.content["restart"].as_bool().unwrap_or(false)
+            })),
+
+            _ => None,
+        };
+
+        if let Some(content) = reply_content {
+            let reply_type = msg_type.replace("_request", "_reply");
+            let frames = self.to_frames(&request.identities, &reply_type, &request.header, content);
+            zmtp::write_message(stream, &frames)?;
+        }
+
+        self.publish_status(&request.header, "idle");
+
+        if msg_type == "shutdown_request" {
+            cmd::terminate_running(self.term_grace_period);
+            process::exit(0);
+        }
+
+        Ok(())
+    }
+
+    fn kernel_info(&self) -> Value {
+        json!({
+            "status": "ok",
+            "protocol_version": PROTOCOL_VERSION,
+            "implementation": "code-runner",
+            "implementation_version": env!("CARGO_PKG_VERSION"),
+            "language_info": {
+                "name": self.language,
+                "version": "",
+                "mimetype": "text/plain",
+                "file_extension": self.file_extension
+            },
+            "banner": format!("code-runner {}", self.language)
+        })
+    }
+
+    fn execute_code(&self, parent_header: &Value, code: &str) -> Value {
+        let mut execution_count = self
+            .execution_count
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        *execution_count += 1;
+
+        self.publish(
+            parent_header,
+            "execute_input",
+            json!({"code": code, "execution_count": *execution_count}),
+        );
+
+        let output = (self.execute)(code);
+
+        for (name, text) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if !text.is_empty() {
+                self.publish(parent_header, "stream", json!({"name": name, "text": text}));
+            }
+        }
+
+        if output.error.is_empty() {
+            return json!({
+                "status": "ok",
+                "execution_count": *execution_count,
+                "user_expressions": {},
+                "payload": []
+            });
+        }
+
+        let error = json!({
+            "ename": "Error",
+            "evalue": output.error,
+            "traceback": [output.error]
+        });
+
+        self.publish(parent_header, "error", error.clone());
+
+        json!({
+            "status": "error",
+            "execution_count": *execution_count,
+            "ename": error["ename"],
+            "evalue": error["evalue"],
+            "traceback": error["traceback"]
+        })
+    }
+
+    fn publish_status(&self, parent_header: &Value, execution_state: &str) {
+        self.publish(
+            parent_header,
+            "status",
+            json!({"execution_state": execution_state}),
+        );
+    }
+
+    // Subscribers that can't be written to are removed
+    fn publish(&self, parent_header: &Value, msg_type: &str, content: Value) {
+        let topic = format!("kernel.{}.{}", self.session, msg_type).into_bytes();
+        let frames = self.to_frames(&[topic], msg_type, parent_header, content);
+
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain_mut(|subscriber| zmtp::write_message(subscriber, &frames).is_ok());
+    }
+
+    fn parse_message(&self, frames: Vec<Vec<u8>>) -> Option<Message> {
+        let delimiter_index = frames.iter().position(|frame| frame == DELIMITER)?;
+        let parts = frames.get(delimiter_index + 1..delimiter_index + 6)?;
+
+        if !signature_matches(&parts[0], self.sign(&parts[1..]).as_bytes()) {
+            return None;
+        }
+
+        Some(Message {
+            identities: frames[..delimiter_index].to_vec(),
+            header: serde_json::from_slice(&parts[1]).ok()?,
+            content: serde_json::from_slice(&parts[4]).ok()?,
+        })
+    }
+
+    fn to_frames(
+        &self,
+        identities: &[Vec<u8>],
+        msg_type: &str,
+        parent_header: &Value,
+        content: Value,
+    ) -> Vec<Vec<u8>> {
+        let message_number = self.message_count.fetch_add(1, atomic::Ordering::SeqCst);
+
+        let header = json!({
+            "msg_id": format!("{}_{}", self.session, message_number),
+            "session": self.session,
+            "username": "kernel",
+            "date": iso8601_now(),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION
+        });
+
+        let parts = vec![
+            header.to_string().into_bytes(),
+            parent_header.to_string().into_bytes(),
+            b"{}".to_vec(),
+            content.to_string().into_bytes(),
+        ];
+
+        let mut frames = identities.to_vec();
+        frames.push(DELIMITER.to_vec());
+        frames.push(self.sign(&parts).into_bytes());
+        frames.extend(parts);
+        frames
+    }
+
+    // Messages are not signed if the key is empty
+    fn sign(&self, parts: &[Vec<u8>]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+
+        hmac_hex_digest(&self.key, parts)
+    }
+}
+
+// HMAC-SHA256 of the concatenated parts as a lowercase hex string
+fn hmac_hex_digest(key: &[u8], parts: &[Vec<u8>]) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("valid hmac key");
+
+    for part in parts {
+        mac.update(part);
+    }
+
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+// Compares all bytes regardless of where they differ, so the time it takes
+// doesn't reveal how much of a forged signature is correct
+fn signature_matches(signature: &[u8], expected: &[u8]) -> bool {
+    signature.len() == expected.len()
+        && signature
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn new_session_id() -> String {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+
+    format!("{:x}{:x}", nanos, process::id())
+}
+
+// Current UTC time, i.e. 2024-01-31T12:00:00.000000Z
+fn iso8601_now() -> String {
+    let duration = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let seconds = duration.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        duration.subsec_micros()
+    )
+}
+
+// Convert days since 1970-01-01 to a (year, month, day) date,
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+pub enum Error {
+    ReadConnectionFile(path::PathBuf, io::Error),
+    ParseConnectionFile(path::PathBuf, serde_json::Error),
+    UnsupportedTransport(String),
+    Bind(String, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ReadConnectionFile(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to read connection file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::ParseConnectionFile(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to parse connection file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::UnsupportedTransport(transport) => {
+                write!(
+                    f,
+                    "Unsupported transport: '{}', only tcp is supported",
+                    transport
+                )
+            }
+
+            Error::Bind(address, err) => {
+                write!(f, "Failed to listen on '{}'. {}", address, err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_matches_only_equal_bytes() {
+        assert!(signature_matches(b"", b""));
+        assert!(signature_matches(b"abc", b"abc"));
+        assert!(!signature_matches(b"abd", b"abc"));
+        assert!(!signature_matches(b"ab", b"abc"));
+        assert!(!signature_matches(b"abcd", b"abc"));
+    }
+
+    // Test case 2 of RFC 4231, split into parts like the frames of a message
+    #[test]
+    fn hmac_known_answer() {
+        let parts = vec![b"what do ya want ".to_vec(), b"for nothing?".to_vec()];
+
+        assert_eq!(
+            hmac_hex_digest(b"Jefe", &parts),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -1,14 +1,22 @@
+use crate::executable;
 use crate::non_empty_vec;
+use crate::shell;
 use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
 use std::path;
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, Debug, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Language {
+    Apl,
     Assembly,
     Ats,
+    Ballerina,
+    Basic,
     Bash,
     C,
+    Carp,
     Clisp,
     Clojure,
     Cobol,
@@ -16,100 +24,563 @@ pub enum Language {
     Cpp,
     Crystal,
     Csharp,
+    Cue,
     D,
     Dart,
+    Dhall,
+    Eiffel,
     Elixir,
     Elm,
     Erlang,
+    Fennel,
+    Forth,
     Fsharp,
     Go,
     Groovy,
     Guile,
     Hare,
     Haskell,
+    Hy,
+    Icon,
     Idris,
     Java,
     JavaScript,
+    Jsonnet,
     Julia,
+    K,
     Kotlin,
+    Lfe,
+    LiveScript,
     Lua,
     Mercury,
+    Modula2,
+    Nickel,
     Nim,
     Nix,
+    Nu,
     Ocaml,
     Pascal,
     Perl,
     Php,
     Python,
     Raku,
+    Red,
+    Rexx,
     Ruby,
     Rust,
     SaC,
     Scala,
     Swift,
     TypeScript,
+    Uiua,
+    Verilog,
+    Wasm,
+    Wren,
     Zig,
+    Zsh,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RunInstructions {
     pub build_commands: Vec<String>,
     pub run_command: String,
 }
 
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: path::PathBuf,
+    pub content: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    #[serde(default)]
+    pub build_flags: Vec<String>,
+    #[serde(default)]
+    pub link_libs: Vec<String>,
+    pub language_version: Option<String>,
+    pub runtime: Option<Runtime>,
+    pub main_class: Option<String>,
+    pub cpp_standard: Option<CppStandard>,
+    pub rust_edition: Option<RustEdition>,
+    pub rust_opt_level: Option<RustOptLevel>,
+    pub target: Option<Target>,
+    // Set from the config, not the request
+    #[serde(skip)]
+    pub compile_daemon: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    Cpython,
+    Pypy,
+    Node,
+    Deno,
+    Bun,
+    Runghc,
+    Ghc,
+    Lua,
+    Luajit,
+    Luau,
+    Mono,
+    Dotnet,
+    Clang,
+    Gcc,
+    Tsc,
+    Tsx,
+    #[serde(rename = "ts-node")]
+    TsNode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Target {
+    #[serde(rename = "x86_64-nasm")]
+    X86_64Nasm,
+    #[serde(rename = "x86_64-gas")]
+    X86_64Gas,
+    #[serde(rename = "aarch64")]
+    Aarch64,
+    #[serde(rename = "wasm32-wasi")]
+    Wasm32Wasi,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum CppStandard {
+    #[serde(rename = "c++11")]
+    Cpp11,
+    #[serde(rename = "c++14")]
+    Cpp14,
+    #[serde(rename = "c++17")]
+    Cpp17,
+    #[serde(rename = "c++20")]
+    Cpp20,
+    #[serde(rename = "c++23")]
+    Cpp23,
+}
+
+impl fmt::Display for CppStandard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CppStandard::Cpp11 => "c++11",
+            CppStandard::Cpp14 => "c++14",
+            CppStandard::Cpp17 => "c++17",
+            CppStandard::Cpp20 => "c++20",
+            CppStandard::Cpp23 => "c++23",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum RustEdition {
+    #[serde(rename = "2015")]
+    E2015,
+    #[serde(rename = "2018")]
+    E2018,
+    #[serde(rename = "2021")]
+    E2021,
+    #[serde(rename = "2024")]
+    E2024,
+}
+
+impl fmt::Display for RustEdition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RustEdition::E2015 => "2015",
+            RustEdition::E2018 => "2018",
+            RustEdition::E2021 => "2021",
+            RustEdition::E2024 => "2024",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum RustOptLevel {
+    #[serde(rename = "0")]
+    O0,
+    #[serde(rename = "1")]
+    O1,
+    #[serde(rename = "2")]
+    O2,
+    #[serde(rename = "3")]
+    O3,
+    #[serde(rename = "s")]
+    Os,
+    #[serde(rename = "z")]
+    Oz,
+}
+
+impl fmt::Display for RustOptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RustOptLevel::O0 => "0",
+            RustOptLevel::O1 => "1",
+            RustOptLevel::O2 => "2",
+            RustOptLevel::O3 => "3",
+            RustOptLevel::Os => "s",
+            RustOptLevel::Oz => "z",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+const PYTHON_WHEEL_CACHE: &str = "/wheel-cache";
+const NPM_CACHE: &str = "/npm-cache";
+const COMPOSER_CACHE: &str = "/composer-cache";
+const MAVEN_REPOSITORY: &str = "/maven-repository";
+const WASI_SYSROOT: &str = "/opt/wasi-sysroot";
+// Carp needs its core library, which is found through CARP_DIR
+const CARP_DIR: &str = "/opt/carp";
+// The rust target was renamed from wasm32-wasi
+const RUST_WASI_TARGET: &str = "wasm32-wasip1";
+const ES_MODULE_PACKAGE: &str = r#"{"type": "module"}"#;
+
+// Minimal project file for `dotnet build`, %s is replaced with the major version of the sdk
+const CSHARP_PROJECT: &str = r#"<Project Sdk="Microsoft.NET.Sdk">
+  <PropertyGroup>
+    <OutputType>Exe</OutputType>
+    <TargetFramework>net%s.0</TargetFramework>
+    <LangVersion>latest</LangVersion>
+    <ImplicitUsings>enable</ImplicitUsings>
+    <Nullable>enable</Nullable>
+  </PropertyGroup>
+</Project>
+"#;
+
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Runtime::Cpython => "cpython",
+            Runtime::Pypy => "pypy",
+            Runtime::Node => "node",
+            Runtime::Deno => "deno",
+            Runtime::Bun => "bun",
+            Runtime::Runghc => "runghc",
+            Runtime::Ghc => "ghc",
+            Runtime::Lua => "lua",
+            Runtime::Luajit => "luajit",
+            Runtime::Luau => "luau",
+            Runtime::Mono => "mono",
+            Runtime::Dotnet => "dotnet",
+            Runtime::Clang => "clang",
+            Runtime::Gcc => "gcc",
+            Runtime::Tsc => "tsc",
+            Runtime::Tsx => "tsx",
+            Runtime::TsNode => "ts-node",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// Runtimes available for each language, the first one is the default
+fn runtimes(language: &Language) -> &'static [Runtime] {
+    match language {
+        Language::C => &[Runtime::Clang, Runtime::Gcc],
+        Language::Cpp => &[Runtime::Clang, Runtime::Gcc],
+        Language::Csharp => &[Runtime::Mono, Runtime::Dotnet],
+        Language::Haskell => &[Runtime::Runghc, Runtime::Ghc],
+        Language::JavaScript => &[Runtime::Node, Runtime::Deno, Runtime::Bun],
+        Language::Lua => &[Runtime::Lua, Runtime::Luajit, Runtime::Luau],
+        Language::Python => &[Runtime::Cpython, Runtime::Pypy],
+        Language::TypeScript => &[
+            Runtime::Tsc,
+            Runtime::Tsx,
+            Runtime::TsNode,
+            Runtime::Deno,
+            Runtime::Bun,
+        ],
+        _ => &[],
+    }
+}
+
+fn select_runtime(language: &Language, runtime: Option<Runtime>) -> Result<Option<Runtime>, Error> {
+    let available_runtimes = runtimes(language);
+
+    match runtime {
+        Some(runtime) if available_runtimes.contains(&runtime) => Ok(Some(runtime)),
+
+        Some(runtime) => Err(Error::RuntimeNotAvailable(
+            runtime,
+            available_runtimes.to_vec(),
+        )),
+
+        None => Ok(available_runtimes.first().copied()),
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Target::X86_64Nasm => "x86_64-nasm",
+            Target::X86_64Gas => "x86_64-gas",
+            Target::Aarch64 => "aarch64",
+            Target::Wasm32Wasi => "wasm32-wasi",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// Targets available for each language, the language decides the default
+fn targets(language: &Language) -> &'static [Target] {
+    match language {
+        Language::Assembly => &[Target::X86_64Nasm, Target::X86_64Gas, Target::Aarch64],
+        Language::C => &[Target::Wasm32Wasi],
+        Language::Rust => &[Target::Wasm32Wasi],
+        Language::Zig => &[Target::Wasm32Wasi],
+        _ => &[],
+    }
+}
+
+fn select_target(language: &Language, target: Option<Target>) -> Result<Option<Target>, Error> {
+    let available_targets = targets(language);
+
+    match target {
+        Some(target) if available_targets.contains(&target) => Ok(Some(target)),
+
+        Some(target) => Err(Error::UnsupportedTarget(target, available_targets.to_vec())),
+
+        None => Ok(None),
+    }
+}
+
 pub fn run_instructions(
     language: &Language,
-    files: non_empty_vec::NonEmptyVec<path::PathBuf>,
-) -> RunInstructions {
-    let (main_file, other_files) = files.parts();
-    let main_file_str = main_file.to_string_lossy();
+    files: non_empty_vec::NonEmptyVec<SourceFile>,
+    options: &Options,
+) -> Result<RunInstructions, Error> {
+    let (main_source, other_sources) = files.parts();
+    let main_file = &main_source.path;
+    let main_file_str = shell::quote(&main_file.to_string_lossy());
 
-    match language {
-        Language::Assembly => RunInstructions {
-            build_commands: vec![
-                format!("nasm -f elf64 -o a.o {}", main_file_str),
-                "ld -o a.out a.o".to_string(),
-            ],
-            run_command: "./a.out".to_string(),
+    let other_files = other_sources
+        .iter()
+        .map(|source| source.path.to_path_buf())
+        .collect::<Vec<path::PathBuf>>();
+
+    // Flags are added right after the compiler, or the interpreter if there is no build step
+    let flags = leading_space_separated(&options.build_flags);
+
+    // Libraries to link with, only used by C, C++ and assembly
+    let libs = leading_space_separated(
+        &options
+            .link_libs
+            .iter()
+            .map(|lib| format!("-l{}", lib))
+            .collect::<Vec<String>>(),
+    );
+
+    let runtime = match (language, options.runtime) {
+        // runghc is slow for programs split into several modules, compile them instead
+        (Language::Haskell, None) if !filter_by_extension(other_files.clone(), "hs").is_empty() => {
+            Some(Runtime::Ghc)
+        }
+
+        (Language::Lua, None) if is_luau_file(main_file) => Some(Runtime::Luau),
+
+        _ => select_runtime(language, options.runtime)?,
+    };
+
+    let target = select_target(language, options.target)?;
+
+    // Resolve binary names to the requested language version
+    let bin = |name: &str| versioned_binary(name, options.language_version.as_deref());
+
+    let run_instructions = match language {
+        // The script is read before stdin, so ⎕ and ⍞ read the stdin of the run
+        Language::Apl => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} --script{} {}", bin("apl")?, flags, main_file_str),
+        },
+
+        Language::Assembly => match target {
+            Some(Target::X86_64Gas) => RunInstructions {
+                build_commands: vec![
+                    format!(
+                        "{}{} --64 -o a.o {} {}",
+                        bin("as")?,
+                        flags,
+                        main_file_str,
+                        space_separated_files(filter_by_extensions(other_files, &["s", "S"]))
+                    ),
+                    link_assembly("ld", "gcc", &libs),
+                ],
+                run_command: "./a.out".to_string(),
+            },
+
+            // Use the native tools on aarch64 hosts, otherwise cross assemble and run with qemu
+            Some(Target::Aarch64) if std::env::consts::ARCH == "aarch64" => RunInstructions {
+                build_commands: vec![
+                    format!(
+                        "{}{} -o a.o {} {}",
+                        bin("as")?,
+                        flags,
+                        main_file_str,
+                        source_files(other_files, "s")
+                    ),
+                    link_assembly("ld", "gcc", &libs),
+                ],
+                run_command: "./a.out".to_string(),
+            },
+
+            Some(Target::Aarch64) => RunInstructions {
+                build_commands: vec![
+                    format!(
+                        "{}{} -o a.o {} {}",
+                        bin("aarch64-linux-gnu-as")?,
+                        flags,
+                        main_file_str,
+                        source_files(other_files, "s")
+                    ),
+                    link_assembly(
+                        "aarch64-linux-gnu-ld",
+                        "aarch64-linux-gnu-gcc",
+                        &libs,
+                    ),
+                ],
+                // The dynamic linker and the libraries of the target are in its sysroot
+                run_command: if libs.is_empty() {
+                    "qemu-aarch64 ./a.out".to_string()
+                } else {
+                    "qemu-aarch64 -L /usr/aarch64-linux-gnu ./a.out".to_string()
+                },
+            },
+
+            _ => RunInstructions {
+                build_commands: vec![
+                    format!(
+                        "{}{} -f elf64 -o a.o {}",
+                        bin("nasm")?,
+                        flags,
+                        main_file_str
+                    ),
+                    link_assembly("ld", "gcc", &libs),
+                ],
+                run_command: "./a.out".to_string(),
+            },
         },
 
         Language::Ats => RunInstructions {
             build_commands: vec![format!(
-                "patscc -o a.out {} {}",
+                "{}{} -o a.out {} {}",
+                bin("patscc")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "dats")
             )],
             run_command: "./a.out".to_string(),
         },
 
+        Language::Ballerina => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} run{} {}", bin("bal")?, flags, main_file_str),
+        },
+
+        Language::Basic => RunInstructions {
+            build_commands: vec![format!(
+                "{}{} -x a.out {} {}",
+                bin("fbc")?,
+                flags,
+                main_file_str,
+                source_files(other_files, "bas")
+            )],
+            run_command: "./a.out".to_string(),
+        },
+
         Language::Bash => RunInstructions {
             build_commands: vec![],
-            run_command: format!("bash {}", main_file_str),
+            run_command: format!("{}{} {}", bin("bash")?, flags, main_file_str),
+        },
+
+        // WebAssembly modules are run with wasmtime, with access to the work dir only
+        Language::C if target == Some(Target::Wasm32Wasi) => RunInstructions {
+            build_commands: vec![format!(
+                "{} --target=wasm32-wasi --sysroot={}{} -o a.wasm {} {}{}",
+                bin("clang")?,
+                WASI_SYSROOT,
+                flags,
+                main_file_str,
+                source_files(other_files, "c"),
+                libs
+            )],
+            run_command: "wasmtime run --dir . a.wasm".to_string(),
         },
 
         Language::C => RunInstructions {
             build_commands: vec![format!(
-                "clang -o a.out -lm {} {}",
+                "{}{} -o a.out -lm {} {}{}",
+                match runtime {
+                    Some(Runtime::Gcc) => bin("gcc")?,
+                    _ => bin("clang")?,
+                },
+                flags,
                 main_file_str,
-                source_files(other_files, "c")
+                source_files(other_files, "c"),
+                libs
             )],
             run_command: "./a.out".to_string(),
         },
 
+        // -x compiles the program to C, builds it and runs it
+        Language::Carp => RunInstructions {
+            build_commands: vec![],
+            run_command: format!(
+                "CARP_DIR={} {} -x{} {}",
+                CARP_DIR,
+                bin("carp")?,
+                flags,
+                main_file_str
+            ),
+        },
+
         Language::Clisp => RunInstructions {
             build_commands: vec![],
-            run_command: format!("sbcl --noinform --non-interactive --load {}", main_file_str),
+            run_command: format!(
+                "{}{} --noinform --non-interactive --load {}",
+                bin("sbcl")?,
+                flags,
+                main_file_str
+            ),
         },
 
+        // Dependencies are resolved from the maven repository in the image.
+        // The namespace of the main file is run, or the main file as a script if it has none
+        Language::Clojure if has_file(&main_source, &other_sources, "deps.edn") => {
+            let main_opt = match clojure_namespace(&main_source.content) {
+                Some(namespace) => format!("-m {}", shell::quote(&namespace)),
+                None => main_file_str,
+            };
+
+            RunInstructions {
+                build_commands: vec![],
+                run_command: format!(
+                    "{} -Sdeps {} -M{} {}",
+                    bin("clj")?,
+                    shell::quote(&format!("{{:mvn/local-repo \"{}\"}}", MAVEN_REPOSITORY)),
+                    flags,
+                    main_opt
+                ),
+            }
+        }
+
         Language::Clojure => RunInstructions {
             build_commands: vec![],
-            run_command: format!("clj -M {}", main_file_str),
+            run_command: format!("{}{} -M {}", bin("clj")?, flags, main_file_str),
         },
 
         Language::Cobol => RunInstructions {
             build_commands: vec![format!(
-                "cobc -x -o a.out {} {}",
+                "{}{} -x -o a.out {} {}",
+                bin("cobc")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "cob")
             )],
@@ -118,35 +589,77 @@ pub fn run_instructions(
 
         Language::CoffeeScript => RunInstructions {
             build_commands: vec![],
-            run_command: format!("coffee {}", main_file_str),
+            run_command: format!("{}{} {}", bin("coffee")?, flags, main_file_str),
         },
 
         Language::Cpp => RunInstructions {
             build_commands: vec![format!(
-                "clang++ -std=c++11 -o a.out {} {}",
+                "{}{} -std={} -o a.out {} {}{}",
+                match runtime {
+                    Some(Runtime::Gcc) => bin("g++")?,
+                    _ => bin("clang++")?,
+                },
+                flags,
+                options.cpp_standard.unwrap_or(CppStandard::Cpp11),
                 main_file_str,
-                source_files(other_files, "c")
+                space_separated_files(filter_by_extensions(other_files, &["cpp", "cc", "cxx"])),
+                libs
             )],
             run_command: "./a.out".to_string(),
         },
 
         Language::Crystal => RunInstructions {
             build_commands: vec![],
-            run_command: format!("crystal run {}", main_file_str),
+            run_command: format!("{} run{} {}", bin("crystal")?, flags, main_file_str),
         },
 
-        Language::Csharp => RunInstructions {
-            build_commands: vec![format!(
-                "mcs -out:a.exe {} {}",
+        Language::Csharp => match runtime {
+            Some(Runtime::Dotnet) => {
+                let dotnet = bin("dotnet")?;
+
+                RunInstructions {
+                    build_commands: vec![
+                        // The project includes all .cs files in the work dir
+                        format!(
+                            "printf {} \"$({} --version | cut -d. -f1)\" > a.csproj",
+                            shell::quote(CSHARP_PROJECT),
+                            dotnet
+                        ),
+                        format!("{} build{} --nologo -v q -o out a.csproj", dotnet, flags),
+                    ],
+                    run_command: format!("{} out/a.dll", dotnet),
+                }
+            }
+
+            _ => RunInstructions {
+                build_commands: vec![format!(
+                    "{}{} -out:a.exe {} {}",
+                    bin("mcs")?,
+                    flags,
+                    main_file_str,
+                    source_files(other_files, "cs")
+                )],
+                run_command: "mono a.exe".to_string(),
+            },
+        },
+
+        // The files are unified into one configuration
+        Language::Cue => RunInstructions {
+            build_commands: vec![],
+            run_command: format!(
+                "{} eval{} {} {}",
+                bin("cue")?,
+                flags,
                 main_file_str,
-                source_files(other_files, "cs")
-            )],
-            run_command: "mono a.exe".to_string(),
+                source_files(other_files, "cue")
+            ),
         },
 
         Language::D => RunInstructions {
             build_commands: vec![format!(
-                "dmd -ofa.out {} {}",
+                "{}{} -ofa.out {} {}",
+                bin("dmd")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "d")
             )],
@@ -155,29 +668,118 @@ pub fn run_instructions(
 
         Language::Dart => RunInstructions {
             build_commands: vec![],
-            run_command: format!("dart {}", main_file_str),
+            run_command: format!("{}{} {}", bin("dart")?, flags, main_file_str),
         },
 
+        // Prints the type checked and normalized expression
+        Language::Dhall => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} --file {}", bin("dhall")?, flags, main_file_str),
+        },
+
+        // gec compiles the root class of the file into an executable named after the class,
+        // the file is expected to be named after its class as is the eiffel convention
+        Language::Eiffel => {
+            let class_name = main_file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("main")
+                .to_ascii_lowercase();
+
+            RunInstructions {
+                build_commands: vec![format!("{}{} {}", bin("gec")?, flags, main_file_str)],
+                run_command: shell::quote(&format!("./{}", class_name)),
+            }
+        }
+
+        // Mix project, hex packages are expected to be available in the image
+        Language::Elixir if has_file(&main_source, &other_sources, "mix.exs") => {
+            let mix = "MIX_ENV=prod HEX_OFFLINE=1 mix";
+
+            // Scripts are run after the application is started, otherwise only the application is run
+            let run_command = match main_file.extension().and_then(|s| s.to_str()) {
+                Some("exs") => format!("{} run{} {}", mix, flags, main_file_str),
+                _ => format!("{} run{}", mix, flags),
+            };
+
+            RunInstructions {
+                build_commands: vec![
+                    format!("{} deps.get --only prod", mix),
+                    format!("{} compile", mix),
+                ],
+                run_command,
+            }
+        }
+
+        // The other files are required before the main file is run
         Language::Elixir => RunInstructions {
             build_commands: vec![],
             run_command: format!(
-                "elixirc {} {}",
-                main_file_str,
-                source_files(other_files, "ex")
+                "{}{}{} {}",
+                bin("elixir")?,
+                flags,
+                filter_by_extensions(other_files, &["ex", "exs"])
+                    .iter()
+                    .map(|file| format!(" -r {}", shell::quote(&file.to_string_lossy())))
+                    .collect::<String>(),
+                main_file_str
             ),
         },
 
         Language::Elm => RunInstructions {
-            build_commands: vec![format!("elm make --output a.js {}", main_file_str)],
+            build_commands: vec![format!(
+                "{} make{} --output a.js {}",
+                bin("elm")?,
+                flags,
+                main_file_str
+            )],
             run_command: "elm-runner a.js".to_string(),
         },
 
-        Language::Erlang => RunInstructions {
-            build_commands: filter_by_extension(other_files, "erl")
-                .iter()
-                .map(|file| format!("erlc {}", file.to_string_lossy()))
-                .collect(),
-            run_command: format!("escript {}", main_file_str),
+        // Rebar3 project, the application is built as an escript named after the application
+        Language::Erlang if has_file(&main_source, &other_sources, "rebar.config") => {
+            let app_name = erlang_app_name(&main_source, &other_sources).unwrap_or_else(|| {
+                main_file
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("main")
+                    .to_string()
+            });
+
+            RunInstructions {
+                build_commands: vec![format!("rebar3 escriptize{}", flags)],
+                run_command: shell::quote(&format!("_build/default/bin/{}", app_name)),
+            }
+        }
+
+        Language::Erlang => {
+            let erlc = bin("erlc")?;
+
+            RunInstructions {
+                build_commands: filter_by_extension(other_files, "erl")
+                    .iter()
+                    .map(|file| {
+                        format!(
+                            "{}{} {}",
+                            erlc,
+                            flags,
+                            shell::quote(&file.to_string_lossy())
+                        )
+                    })
+                    .collect(),
+                run_command: format!("{} {}", bin("escript")?, main_file_str),
+            }
+        }
+
+        Language::Fennel => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("fennel")?, flags, main_file_str),
+        },
+
+        // Exit after loading the file instead of starting the interactive interpreter
+        Language::Forth => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {} -e bye", bin("gforth")?, flags, main_file_str),
         },
 
         Language::Fsharp => {
@@ -186,7 +788,9 @@ pub fn run_instructions(
 
             RunInstructions {
                 build_commands: vec![format!(
-                    "fsharpc --out:a.exe {} {}",
+                    "{}{} --out:a.exe {} {}",
+                    bin("fsharpc")?,
+                    flags,
                     space_separated_files(source_files),
                     main_file_str
                 )],
@@ -194,59 +798,195 @@ pub fn run_instructions(
             }
         }
 
+        // Go module, build all packages and then the package of the main file
+        Language::Go if has_file(&main_source, &other_sources, "go.mod") => {
+            let go = bin("go")?;
+
+            let main_package = main_file
+                .parent()
+                .map(|dir| path::Path::new(".").join(dir))
+                .unwrap_or_else(|| path::PathBuf::from("."));
+
+            RunInstructions {
+                build_commands: vec![
+                    format!("{} build{} ./...", go, flags),
+                    format!(
+                        "{} build{} -o a.out {}",
+                        go,
+                        flags,
+                        shell::quote(&main_package.to_string_lossy())
+                    ),
+                ],
+                run_command: "./a.out".to_string(),
+            }
+        }
+
         Language::Go => RunInstructions {
-            build_commands: vec![format!("go build -o a.out {}", main_file_str)],
+            build_commands: vec![format!(
+                "{} build{} -o a.out {}",
+                bin("go")?,
+                flags,
+                main_file_str
+            )],
             run_command: "./a.out".to_string(),
         },
 
         Language::Groovy => RunInstructions {
             build_commands: vec![],
-            run_command: format!("groovy {}", main_file_str),
+            run_command: format!("{}{} {}", bin("groovy")?, flags, main_file_str),
         },
 
         Language::Guile => RunInstructions {
             build_commands: vec![],
             run_command: format!(
-                "guile --no-debug --fresh-auto-compile --no-auto-compile -s {}",
+                "{}{} --no-debug --fresh-auto-compile --no-auto-compile -s {}",
+                bin("guile")?,
+                flags,
                 main_file_str
             ),
         },
 
         Language::Hare => RunInstructions {
-            build_commands: vec![format!("hare build -o a.out {}", main_file_str)],
+            build_commands: vec![format!(
+                "{} build{} -o a.out {}",
+                bin("hare")?,
+                flags,
+                main_file_str
+            )],
             run_command: "./a.out".to_string(),
         },
 
-        Language::Haskell => RunInstructions {
+        Language::Haskell => match runtime {
+            Some(Runtime::Ghc) => RunInstructions {
+                build_commands: vec![format!(
+                    "{} -O2 -outputdir .ghc{} -o a.out {}",
+                    bin("ghc")?,
+                    flags,
+                    main_file_str
+                )],
+                run_command: "./a.out".to_string(),
+            },
+
+            _ => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("runghc")?, flags, main_file_str),
+            },
+        },
+
+        Language::Hy => RunInstructions {
             build_commands: vec![],
-            run_command: format!("runghc {}", main_file_str),
+            run_command: format!("{}{} {}", bin("hy")?, flags, main_file_str),
+        },
+
+        Language::Icon => RunInstructions {
+            build_commands: vec![format!(
+                "{}{} -o a.out {} {}",
+                bin("icont")?,
+                flags,
+                main_file_str,
+                source_files(other_files, "icn")
+            )],
+            run_command: "./a.out".to_string(),
         },
 
         Language::Idris => RunInstructions {
-            build_commands: vec![format!("idris2 -o a.out --output-dir . {}", main_file_str)],
+            build_commands: vec![format!(
+                "{}{} -o a.out --output-dir . {}",
+                bin("idris2")?,
+                flags,
+                main_file_str
+            )],
             run_command: "./a.out".to_string(),
         },
 
         Language::Java => {
-            let file_stem = main_file
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("Main");
+            // Fall back to the titlecased file name if no main method is found
+            let main_class = options
+                .main_class
+                .clone()
+                .or_else(|| java_main_class(&main_source, &other_sources))
+                .unwrap_or_else(|| {
+                    let file_stem = main_file
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or("Main");
+
+                    titlecase_ascii(file_stem)
+                });
+
+            RunInstructions {
+                build_commands: vec![format!(
+                    "{}{} -d . {} {}",
+                    bin("javac")?,
+                    flags,
+                    main_file_str,
+                    source_files(other_files, "java")
+                )],
+                run_command: format!("{} {}", bin("java")?, shell::quote(&main_class)),
+            }
+        }
 
+        // Install dependencies from the npm cache in the image
+        Language::JavaScript
+            if runtime == Some(Runtime::Node)
+                && has_file(&main_source, &other_sources, "package.json") =>
+        {
             RunInstructions {
-                build_commands: vec![format!("javac {}", main_file_str)],
-                run_command: format!("java {}", titlecase_ascii(file_stem)),
+                build_commands: vec![npm_install(&main_source, &other_sources, "npm")],
+                run_command: format!("{}{} {}", bin("node")?, flags, main_file_str),
             }
         }
 
-        Language::JavaScript => RunInstructions {
+        Language::JavaScript => match runtime {
+            Some(Runtime::Deno) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{} run --quiet{} {}", bin("deno")?, flags, main_file_str),
+            },
+
+            Some(Runtime::Bun) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{} run{} {}", bin("bun")?, flags, main_file_str),
+            },
+
+            _ => {
+                let extension = main_file.extension().and_then(|s| s.to_str());
+
+                // Node treats .js files as commonjs unless the nearest package.json says otherwise
+                let build_commands = if extension == Some("js")
+                    && is_es_module(&main_source, &other_sources)
+                    && !has_file(&main_source, &other_sources, "package.json")
+                {
+                    vec![format!(
+                        "printf {} > package.json",
+                        shell::quote(ES_MODULE_PACKAGE)
+                    )]
+                } else {
+                    vec![]
+                };
+
+                RunInstructions {
+                    build_commands,
+                    run_command: format!("{}{} {}", bin("node")?, flags, main_file_str),
+                }
+            }
+        },
+
+        // Imports are resolved relative to the importing file, -J . also finds
+        // .libsonnet files in the work dir from files in subdirectories
+        Language::Jsonnet => RunInstructions {
             build_commands: vec![],
-            run_command: format!("node {}", main_file_str),
+            run_command: format!("{} -J .{} {}", bin("jsonnet")?, flags, main_file_str),
         },
 
         Language::Julia => RunInstructions {
             build_commands: vec![],
-            run_command: format!("julia {}", main_file_str),
+            run_command: format!("{}{} {}", bin("julia")?, flags, main_file_str),
+        },
+
+        // The ngn/k interpreter
+        Language::K => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("k")?, flags, main_file_str),
         },
 
         Language::Kotlin => {
@@ -256,36 +996,93 @@ pub fn run_instructions(
                 .unwrap_or("Main");
 
             RunInstructions {
-                build_commands: vec![format!("kotlinc {}", main_file_str)],
-                run_command: format!("kotlin {}Kt", titlecase_ascii(file_stem)),
+                build_commands: vec![format!("{}{} {}", bin("kotlinc")?, flags, main_file_str)],
+                run_command: format!(
+                    "{} {}",
+                    bin("kotlin")?,
+                    shell::quote(&format!("{}Kt", titlecase_ascii(file_stem)))
+                ),
             }
         }
 
-        Language::Lua => RunInstructions {
+        Language::Lfe => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("lfescript")?, flags, main_file_str),
+        },
+
+        Language::LiveScript => RunInstructions {
             build_commands: vec![],
-            run_command: format!("lua {}", main_file_str),
+            run_command: format!("{}{} {}", bin("lsc")?, flags, main_file_str),
+        },
+
+        Language::Lua => match runtime {
+            Some(Runtime::Luajit) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("luajit")?, flags, main_file_str),
+            },
+
+            // Type annotations are allowed but not checked when running, see check_instructions
+            Some(Runtime::Luau) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("luau")?, flags, main_file_str),
+            },
+
+            _ => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("lua")?, flags, main_file_str),
+            },
         },
 
         Language::Mercury => RunInstructions {
             build_commands: vec![format!(
-                "mmc -o a.out {} {}",
+                "{}{} -o a.out {} {}",
+                bin("mmc")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "m")
             )],
             run_command: "./a.out".to_string(),
         },
 
+        // Imported modules are found in the work dir by gm2
+        Language::Modula2 => RunInstructions {
+            build_commands: vec![format!(
+                "{}{} -o a.out {}",
+                bin("gm2")?,
+                flags,
+                main_file_str
+            )],
+            run_command: "./a.out".to_string(),
+        },
+
+        Language::Nickel => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} eval{} {}", bin("nickel")?, flags, main_file_str),
+        },
+
         Language::Nim => RunInstructions {
             build_commands: vec![],
             run_command: format!(
-                "nim --hints:off --verbosity:0 compile --run {}",
+                "{}{} --hints:off --verbosity:0 compile --run {}",
+                bin("nim")?,
+                flags,
                 main_file_str
             ),
         },
 
         Language::Nix => RunInstructions {
             build_commands: vec![],
-            run_command: format!("nix-instantiate --eval {}", main_file_str),
+            run_command: format!(
+                "{}{} --eval {}",
+                bin("nix-instantiate")?,
+                flags,
+                main_file_str
+            ),
+        },
+
+        Language::Nu => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("nu")?, flags, main_file_str),
         },
 
         Language::Ocaml => {
@@ -294,7 +1091,9 @@ pub fn run_instructions(
 
             RunInstructions {
                 build_commands: vec![format!(
-                    "ocamlc -o a.out {} {}",
+                    "{}{} -o a.out {} {}",
+                    bin("ocamlc")?,
+                    flags,
                     space_separated_files(source_files),
                     main_file_str
                 )],
@@ -303,43 +1102,166 @@ pub fn run_instructions(
         }
 
         Language::Pascal => RunInstructions {
-            build_commands: vec![format!("fpc -oa.out {}", main_file_str)],
+            build_commands: vec![format!(
+                "{}{} -oa.out {}",
+                bin("fpc")?,
+                flags,
+                main_file_str
+            )],
             run_command: "./a.out".to_string(),
         },
 
         Language::Perl => RunInstructions {
             build_commands: vec![],
-            run_command: format!("perl {}", main_file_str),
+            run_command: format!("{}{} {}", bin("perl")?, flags, main_file_str),
+        },
+
+        // Composer project, packages are installed from the composer cache in the image
+        // and the autoloader is loaded before the main file
+        Language::Php if has_file(&main_source, &other_sources, "composer.json") => RunInstructions {
+            build_commands: vec![format!(
+                "COMPOSER_DISABLE_NETWORK=1 COMPOSER_CACHE_DIR={} composer install --no-interaction --no-progress --quiet",
+                COMPOSER_CACHE
+            )],
+            run_command: format!(
+                "{} -d auto_prepend_file=vendor/autoload.php{} {}",
+                bin("php")?,
+                flags,
+                main_file_str
+            ),
         },
 
         Language::Php => RunInstructions {
             build_commands: vec![],
-            run_command: format!("php {}", main_file_str),
+            run_command: format!("{}{} {}", bin("php")?, flags, main_file_str),
         },
 
-        Language::Python => RunInstructions {
-            build_commands: vec![],
-            run_command: format!("python {}", main_file_str),
+        // Install requirements into a virtualenv from the wheel cache in the image
+        Language::Python if has_file(&main_source, &other_sources, "requirements.txt") => {
+            let python = match runtime {
+                Some(Runtime::Pypy) => bin("pypy3")?,
+                _ => bin("python")?,
+            };
+
+            RunInstructions {
+                build_commands: vec![
+                    format!("{} -m venv .venv", python),
+                    format!(
+                        ".venv/bin/pip install --quiet --no-index --find-links {} -r requirements.txt",
+                        PYTHON_WHEEL_CACHE
+                    ),
+                ],
+                run_command: format!(".venv/bin/python{} {}", flags, main_file_str),
+            }
+        }
+
+        Language::Python => match runtime {
+            Some(Runtime::Pypy) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("pypy3")?, flags, main_file_str),
+            },
+
+            _ => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("python")?, flags, main_file_str),
+            },
         },
 
         Language::Raku => RunInstructions {
             build_commands: vec![],
-            run_command: format!("raku {}", main_file_str),
+            run_command: format!("{}{} {}", bin("raku")?, flags, main_file_str),
+        },
+
+        // Use the command line console instead of the gui console, it exits after the script
+        Language::Red => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} --cli{} {}", bin("red")?, flags, main_file_str),
+        },
+
+        Language::Rexx => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("regina")?, flags, main_file_str),
         },
 
+        // Bundler project, gems are installed from the gems available in the image
+        Language::Ruby if has_file(&main_source, &other_sources, "Gemfile") => {
+            let bundle = "bundle";
+
+            RunInstructions {
+                build_commands: vec![format!("{} install --local --quiet", bundle)],
+                run_command: format!(
+                    "{} exec {}{} {}",
+                    bundle,
+                    bin("ruby")?,
+                    flags,
+                    main_file_str
+                ),
+            }
+        }
+
         Language::Ruby => RunInstructions {
             build_commands: vec![],
-            run_command: format!("ruby {}", main_file_str),
+            run_command: format!("{}{} {}", bin("ruby")?, flags, main_file_str),
+        },
+
+        // Cargo project, dependencies are expected to be vendored in the image
+        Language::Rust if has_file(&main_source, &other_sources, "Cargo.toml") => {
+            let cargo = "cargo";
+
+            // Cargo runs the wasm module with the configured runner
+            let (runner, target_flag) = match target {
+                Some(Target::Wasm32Wasi) => (
+                    format!(
+                        "CARGO_TARGET_WASM32_WASIP1_RUNNER={} ",
+                        shell::quote("wasmtime run --dir .")
+                    ),
+                    format!(" --target {}", RUST_WASI_TARGET),
+                ),
+                _ => (String::new(), String::new()),
+            };
+
+            RunInstructions {
+                build_commands: vec![format!(
+                    "{} build --offline --quiet{}{}",
+                    cargo, target_flag, flags
+                )],
+                run_command: format!(
+                    "{}{} run --offline --quiet{}{}",
+                    runner, cargo, target_flag, flags
+                ),
+            }
+        }
+
+        Language::Rust if target == Some(Target::Wasm32Wasi) => RunInstructions {
+            build_commands: vec![format!(
+                "{} --edition {} -C opt-level={} --target {}{} -o a.wasm {}",
+                bin("rustc")?,
+                options.rust_edition.unwrap_or(RustEdition::E2021),
+                options.rust_opt_level.unwrap_or(RustOptLevel::O0),
+                RUST_WASI_TARGET,
+                flags,
+                main_file_str
+            )],
+            run_command: "wasmtime run --dir . a.wasm".to_string(),
         },
 
         Language::Rust => RunInstructions {
-            build_commands: vec![format!("rustc -o a.out {}", main_file_str)],
+            build_commands: vec![format!(
+                "{} --edition {} -C opt-level={}{} -o a.out {}",
+                bin("rustc")?,
+                options.rust_edition.unwrap_or(RustEdition::E2021),
+                options.rust_opt_level.unwrap_or(RustOptLevel::O0),
+                flags,
+                main_file_str
+            )],
             run_command: "./a.out".to_string(),
         },
 
         Language::SaC => RunInstructions {
             build_commands: vec![format!(
-                "sac2c -t seq -o a.out {} {}",
+                "{}{} -t seq -o a.out {} {}",
+                bin("sac2c")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "c")
             )],
@@ -348,57 +1270,579 @@ pub fn run_instructions(
 
         Language::Scala => RunInstructions {
             build_commands: vec![format!(
-                "scalac {} {}",
+                "{}{} {} {}",
+                scala_compiler(options.compile_daemon, &bin)?,
+                flags,
                 main_file_str,
                 source_files(other_files, "scala")
             )],
-            run_command: "scala Main".to_string(),
+            run_command: format!(
+                "{} {}",
+                bin("scala")?,
+                shell::quote(
+                    &options
+                        .main_class
+                        .clone()
+                        .or_else(|| scala_main_class(&main_source, &other_sources))
+                        .unwrap_or_else(|| "Main".to_string())
+                )
+            ),
         },
 
         Language::Swift => RunInstructions {
             build_commands: vec![format!(
-                "swiftc -o a.out {} {}",
+                "{}{} -o a.out {} {}",
+                bin("swiftc")?,
+                flags,
                 main_file_str,
                 source_files(other_files, "swift")
             )],
             run_command: "./a.out".to_string(),
         },
 
-        Language::TypeScript => RunInstructions {
+        Language::TypeScript => match runtime {
+            // Run the source directly without type checking
+            Some(Runtime::Tsx) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("tsx")?, flags, main_file_str),
+            },
+
+            Some(Runtime::TsNode) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!(
+                    "{} --transpile-only{} {}",
+                    bin("ts-node")?,
+                    flags,
+                    main_file_str
+                ),
+            },
+
+            // Type checked like tsc, without the separate build step
+            Some(Runtime::Deno) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!(
+                    "{} run --quiet --check{} {}",
+                    bin("deno")?,
+                    flags,
+                    main_file_str
+                ),
+            },
+
+            // Transpiled on the fly without type checking
+            Some(Runtime::Bun) => RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{} run{} {}", bin("bun")?, flags, main_file_str),
+            },
+
+            // -outFile only supports amd and system modules, so es modules are compiled
+            // file by file into the out directory
+            _ if is_es_module(&main_source, &other_sources) => {
+                let output_file = path::Path::new("out").join(main_file).with_extension(
+                    match main_file.extension().and_then(|s| s.to_str()) {
+                        Some("mts") => "mjs",
+                        _ => "js",
+                    },
+                );
+
+                RunInstructions {
+                    build_commands: vec![
+                        format!(
+                            "tsc{} --module es2022 --target es2022 --moduleResolution bundler --rootDir . --outDir out {} {}",
+                            flags,
+                            main_file_str,
+                            space_separated_files(filter_by_extensions(other_files, &["ts", "mts"]))
+                        ),
+                        format!("printf {} > out/package.json", shell::quote(ES_MODULE_PACKAGE)),
+                    ],
+                    run_command: format!(
+                        "{} {}",
+                        bin("node")?,
+                        shell::quote(&output_file.to_string_lossy())
+                    ),
+                }
+            }
+
+            _ => RunInstructions {
+                build_commands: vec![format!(
+                    "tsc{} -outFile a.js {} {}",
+                    flags,
+                    main_file_str,
+                    source_files(other_files, "ts")
+                )],
+                run_command: format!("{} a.js", bin("node")?),
+            },
+        },
+
+        Language::Uiua => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} run{} {}", bin("uiua")?, flags, main_file_str),
+        },
+
+        Language::Verilog => RunInstructions {
             build_commands: vec![format!(
-                "tsc -outFile a.js {} {}",
+                "{}{} -o a.out {} {}",
+                bin("iverilog")?,
+                flags,
                 main_file_str,
-                source_files(other_files, "ts")
+                source_files(other_files, "v")
             )],
-            run_command: "node a.js".to_string(),
+            run_command: format!("{} a.out", bin("vvp")?),
+        },
+
+        // The text format is converted to a binary module first
+        Language::Wasm => {
+            let wasmtime = bin("wasmtime")?;
+
+            match main_file.extension().and_then(|s| s.to_str()) {
+                Some("wat") => RunInstructions {
+                    build_commands: vec![format!(
+                        "wat2wasm{} -o a.wasm {}",
+                        flags,
+                        main_file_str
+                    )],
+                    run_command: format!("{} run --dir . a.wasm", wasmtime),
+                },
+
+                _ => RunInstructions {
+                    build_commands: vec![],
+                    run_command: format!("{} run --dir . {}", wasmtime, main_file_str),
+                },
+            }
+        }
+
+        Language::Wren => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("wren_cli")?, flags, main_file_str),
+        },
+
+        Language::Zig if target == Some(Target::Wasm32Wasi) => RunInstructions {
+            build_commands: vec![format!(
+                "{} build-exe -target wasm32-wasi -femit-bin=a.wasm{} {}",
+                bin("zig")?,
+                flags,
+                main_file_str
+            )],
+            run_command: "wasmtime run --dir . a.wasm".to_string(),
         },
 
         Language::Zig => RunInstructions {
             build_commands: vec![],
-            run_command: format!("zig run {}", main_file_str),
+            run_command: format!("{} run{} {}", bin("zig")?, flags, main_file_str),
+        },
+
+        Language::Zsh => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{}{} {}", bin("zsh")?, flags, main_file_str),
+        },
+    };
+
+    Ok(run_instructions)
+}
+
+// Commands that run the tests of the files with the test runner of the language
+pub fn test_instructions(
+    language: &Language,
+    files: non_empty_vec::NonEmptyVec<SourceFile>,
+    options: &Options,
+) -> Result<RunInstructions, Error> {
+    let (main_source, other_sources) = files.parts();
+    let main_file_str = shell::quote(&main_source.path.to_string_lossy());
+    let flags = leading_space_separated(&options.build_flags);
+    let bin = |name: &str| versioned_binary(name, options.language_version.as_deref());
+
+    let test_instructions = match language {
+        Language::Elixir => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("MIX_ENV=test HEX_OFFLINE=1 mix test --trace{}", flags),
+        },
+
+        Language::Go if has_file(&main_source, &other_sources, "go.mod") => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} test -v{} ./...", bin("go")?, flags),
+        },
+
+        Language::Go => RunInstructions {
+            build_commands: vec![],
+            run_command: format!(
+                "{} test -v{} {}",
+                bin("go")?,
+                flags,
+                space_separated_files(
+                    sources_with_extension(&main_source, &other_sources, "go")
+                        .map(|source| source.path.clone())
+                        .collect()
+                )
+            ),
+        },
+
+        Language::JavaScript if has_file(&main_source, &other_sources, "package.json") => {
+            let npm = "npm";
+
+            RunInstructions {
+                build_commands: vec![npm_install(&main_source, &other_sources, npm)],
+                run_command: format!("{} test --silent{}", npm, flags),
+            }
+        }
+
+        Language::JavaScript => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} --test --test-reporter=tap{}", bin("node")?, flags),
+        },
+
+        Language::Python => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("{} -m pytest -v{}", bin("python")?, flags),
+        },
+
+        Language::Rust if has_file(&main_source, &other_sources, "Cargo.toml") => RunInstructions {
+            build_commands: vec![],
+            run_command: format!("cargo test --offline --quiet{}", flags),
+        },
+
+        Language::Rust => RunInstructions {
+            build_commands: vec![format!(
+                "{} --edition {} --test{} -o a.out {}",
+                bin("rustc")?,
+                options.rust_edition.unwrap_or(RustEdition::E2021),
+                flags,
+                main_file_str
+            )],
+            run_command: "./a.out".to_string(),
         },
+
+        _ => return Err(Error::TestsNotSupported()),
+    };
+
+    Ok(test_instructions)
+}
+
+// Type check the program without running it
+pub fn check_instructions(
+    language: &Language,
+    files: non_empty_vec::NonEmptyVec<SourceFile>,
+    options: &Options,
+) -> Result<RunInstructions, Error> {
+    let (main_source, _) = files.parts();
+    let main_file = &main_source.path;
+    let main_file_str = shell::quote(&main_file.to_string_lossy());
+    let flags = leading_space_separated(&options.build_flags);
+    let bin = |name: &str| versioned_binary(name, options.language_version.as_deref());
+
+    let runtime = select_runtime(language, options.runtime)?;
+
+    let check_instructions = match language {
+        // Modules that are required by the main file are checked too
+        Language::Lua if runtime == Some(Runtime::Luau) || is_luau_file(main_file) => {
+            RunInstructions {
+                build_commands: vec![],
+                run_command: format!("{}{} {}", bin("luau-analyze")?, flags, main_file_str),
+            }
+        }
+
+        _ => return Err(Error::ChecksNotSupported()),
+    };
+
+    Ok(check_instructions)
+}
+
+fn is_luau_file(file_path: &path::Path) -> bool {
+    file_path.extension().and_then(|s| s.to_str()) == Some("luau")
+}
+
+// Install the dependencies in package.json from the npm cache in the image
+fn npm_install(main_source: &SourceFile, other_sources: &[SourceFile], npm: &str) -> String {
+    let npm_command = if has_file(main_source, other_sources, "package-lock.json") {
+        "ci"
+    } else {
+        "install"
+    };
+
+    format!(
+        "{} {} --offline --cache {} --no-audit --no-fund --quiet",
+        npm, npm_command, NPM_CACHE
+    )
+}
+
+// Use the scala compile server (fsc) of Scala 2.12 and older if enabled and available. The server
+// stays alive between runs, so the JVM startup cost is only paid once per container. A languageVersion
+// without fsc, i.e. Scala 2.13 or 3, is compiled with scalac. Kotlin is always compiled cold
+fn scala_compiler<F>(compile_daemon: bool, bin: &F) -> Result<String, Error>
+where
+    F: Fn(&str) -> Result<String, Error>,
+{
+    let compile_server = if compile_daemon {
+        bin("fsc")
+            .ok()
+            .filter(|name| executable::find(name).is_some())
+    } else {
+        None
+    };
+
+    match compile_server {
+        Some(name) => Ok(name),
+        None => bin("scalac"),
+    }
+}
+
+fn versioned_binary(name: &str, version: Option<&str>) -> Result<String, Error> {
+    match version {
+        // The version becomes part of a binary name that is looked up in PATH and put into
+        // the command unquoted, so only versions like 3.12 are accepted
+        Some(version)
+            if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit() || c == '.') =>
+        {
+            Err(Error::InvalidVersion(version.to_string()))
+        }
+
+        Some(version) => {
+            let candidates = vec![
+                format!("{}{}", name, version),
+                format!("{}-{}", name, version),
+            ];
+
+            candidates
+                .into_iter()
+                .find(|candidate| executable::find(candidate).is_some())
+                .ok_or_else(|| {
+                    Error::VersionNotAvailable(
+                        name.to_string(),
+                        version.to_string(),
+                        available_versions(name),
+                    )
+                })
+        }
+
+        None => Ok(name.to_string()),
     }
 }
 
+// Find versions of a binary in PATH, i.e. python3.11 and python3.12 gives ["3.11", "3.12"]
+fn available_versions(name: &str) -> Vec<String> {
+    let mut versions = executable::list_names()
+        .iter()
+        .filter_map(|executable_name| executable_name.strip_prefix(name))
+        .map(|suffix| suffix.strip_prefix('-').unwrap_or(suffix))
+        .filter(|version| {
+            version.starts_with(|c: char| c.is_ascii_digit())
+                && version.chars().all(|c| c.is_ascii_digit() || c == '.')
+        })
+        .map(|version| version.to_string())
+        .collect::<Vec<String>>();
+
+    versions.sort_by_key(|version| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0))
+            .collect::<Vec<u32>>()
+    });
+    versions.dedup();
+    versions
+}
+
 fn source_files(files: Vec<path::PathBuf>, extension: &str) -> String {
     space_separated_files(filter_by_extension(files, extension))
 }
 
 fn filter_by_extension(files: Vec<path::PathBuf>, extension: &str) -> Vec<path::PathBuf> {
+    filter_by_extensions(files, &[extension])
+}
+
+fn filter_by_extensions(files: Vec<path::PathBuf>, extensions: &[&str]) -> Vec<path::PathBuf> {
     files
         .into_iter()
-        .filter(|file| file.extension().and_then(|s| s.to_str()) == Some(extension))
+        .filter(|file| {
+            file.extension()
+                .and_then(|s| s.to_str())
+                .is_some_and(|extension| extensions.contains(&extension))
+        })
         .collect()
 }
 
 fn space_separated_files(files: Vec<path::PathBuf>) -> String {
     files
         .iter()
-        .map(|file| file.to_string_lossy().to_string())
+        .map(|file| shell::quote(&file.to_string_lossy()))
         .collect::<Vec<String>>()
         .join(" ")
 }
 
+// Find the fully qualified name of the class with the main method,
+// the main file is searched first
+fn java_main_class(main_source: &SourceFile, other_sources: &[SourceFile]) -> Option<String> {
+    sources_with_extension(main_source, other_sources, "java").find_map(|source| {
+        let main_index = source.content.find("static void main")?;
+        let class_name = last_declared_name(&source.content[..main_index], "class ")?;
+
+        Some(qualified_name(package_name(&source.content), class_name))
+    })
+}
+
+// Find the fully qualified name of the object with a main method or that extends App,
+// or a scala 3 `@main` method. The main file is searched first
+fn scala_main_class(main_source: &SourceFile, other_sources: &[SourceFile]) -> Option<String> {
+    sources_with_extension(main_source, other_sources, "scala").find_map(|source| {
+        let package = package_name(&source.content);
+
+        if let Some(index) = source.content.find("@main def ") {
+            let method_name = identifier(&source.content[index + "@main def ".len()..])?;
+            return Some(qualified_name(package, method_name));
+        }
+
+        let main_index = source
+            .content
+            .find("def main")
+            .or_else(|| source.content.find("extends App"))?;
+
+        let object_name = last_declared_name(&source.content[..main_index], "object ")?;
+
+        Some(qualified_name(package, object_name))
+    })
+}
+
+fn has_file(main_source: &SourceFile, other_sources: &[SourceFile], file_path: &str) -> bool {
+    std::iter::once(main_source)
+        .chain(other_sources.iter())
+        .any(|source| source.path == path::Path::new(file_path))
+}
+
+// The application name is given by the src/<name>.app.src file
+fn erlang_app_name(main_source: &SourceFile, other_sources: &[SourceFile]) -> Option<String> {
+    std::iter::once(main_source)
+        .chain(other_sources.iter())
+        .find_map(|source| {
+            let file_name = source.path.file_name()?.to_str()?;
+            file_name
+                .strip_suffix(".app.src")
+                .map(|name| name.to_string())
+        })
+}
+
+fn clojure_namespace(source: &str) -> Option<String> {
+    let index = source.find("(ns ")?;
+    let namespace = source[index + "(ns ".len()..]
+        .trim_start()
+        .chars()
+        .take_while(|c| !c.is_whitespace() && *c != ')')
+        .collect::<String>();
+
+    if namespace.is_empty() {
+        None
+    } else {
+        Some(namespace)
+    }
+}
+
+// Es modules are detected by the .mjs/.mts extension, `"type": "module"` in package.json
+// or import/export statements in the main file
+fn is_es_module(main_source: &SourceFile, other_sources: &[SourceFile]) -> bool {
+    let extension = main_source.path.extension().and_then(|s| s.to_str());
+
+    match (
+        extension,
+        package_type(main_source, other_sources).as_deref(),
+    ) {
+        (Some("mjs"), _) | (Some("mts"), _) => true,
+        (Some("cjs"), _) | (Some("cts"), _) => false,
+        (_, Some("module")) => true,
+        (_, Some(_)) => false,
+        (_, None) => has_module_syntax(&main_source.content),
+    }
+}
+
+fn package_type(main_source: &SourceFile, other_sources: &[SourceFile]) -> Option<String> {
+    let package = std::iter::once(main_source)
+        .chain(other_sources.iter())
+        .find(|source| source.path == path::Path::new("package.json"))?;
+
+    let value: serde_json::Value = serde_json::from_str(&package.content).ok()?;
+    value.get("type")?.as_str().map(|s| s.to_string())
+}
+
+fn has_module_syntax(source: &str) -> bool {
+    source.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with("import ") || line.starts_with("import{") || line.starts_with("export ")
+    })
+}
+
+fn sources_with_extension<'a>(
+    main_source: &'a SourceFile,
+    other_sources: &'a [SourceFile],
+    extension: &'a str,
+) -> impl Iterator<Item = &'a SourceFile> {
+    std::iter::once(main_source)
+        .chain(other_sources.iter())
+        .filter(move |source| source.path.extension().and_then(|s| s.to_str()) == Some(extension))
+}
+
+fn qualified_name(package: Option<String>, name: String) -> String {
+    match package {
+        Some(package) => format!("{}.{}", package, name),
+        None => name,
+    }
+}
+
+// Find the name of the last declaration of the given kind in the source, i.e. "class "
+fn last_declared_name(source: &str, keyword: &str) -> Option<String> {
+    let index = source
+        .match_indices(keyword)
+        .map(|(index, _)| index)
+        .filter(|index| {
+            source[..*index]
+                .chars()
+                .last()
+                .is_none_or(|c| c.is_whitespace())
+        })
+        .last()?;
+
+    identifier(&source[index + keyword.len()..])
+}
+
+fn identifier(source: &str) -> Option<String> {
+    let name = source
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+        .collect::<String>();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+// Find the package declaration, works for both java and scala
+fn package_name(source: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let package = line.trim().strip_prefix("package ")?;
+        let package = package.split(';').next()?.trim();
+
+        if package.is_empty() {
+            None
+        } else {
+            Some(package.to_string())
+        }
+    })
+}
+
+// ld links libraries dynamically with a default interpreter that doesn't exist, so the
+// program can't start. With libraries the C compiler links it with the dynamic linker of
+// the system, -nostartfiles keeps the _start of the program
+fn link_assembly(linker: &str, compiler: &str, libs: &str) -> String {
+    if libs.is_empty() {
+        format!("{} -o a.out a.o", linker)
+    } else {
+        format!("{} -nostartfiles -no-pie -o a.out a.o{}", compiler, libs)
+    }
+}
+
+fn leading_space_separated(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| format!(" {}", shell::quote(arg)))
+        .collect()
+}
+
 fn titlecase_ascii(s: &str) -> String {
     if !s.is_ascii() || s.len() < 2 {
         s.to_string()
@@ -407,3 +1851,71 @@ fn titlecase_ascii(s: &str) -> String {
         format!("{}{}", head.to_ascii_uppercase(), tail)
     }
 }
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidVersion(String),
+    VersionNotAvailable(String, String, Vec<String>),
+    RuntimeNotAvailable(Runtime, Vec<Runtime>),
+    UnsupportedTarget(Target, Vec<Target>),
+    TestsNotSupported(),
+    ChecksNotSupported(),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidVersion(version) => {
+                write!(
+                    f,
+                    "Invalid language version '{}', only digits and dots are allowed",
+                    version
+                )
+            }
+
+            Error::VersionNotAvailable(name, version, available_versions) => {
+                write!(
+                    f,
+                    "Version '{}' of '{}' is not available, available versions: [{}]",
+                    version,
+                    name,
+                    available_versions.join(", ")
+                )
+            }
+
+            Error::RuntimeNotAvailable(runtime, available_runtimes) => {
+                write!(
+                    f,
+                    "Runtime '{}' is not available for this language, available runtimes: [{}]",
+                    runtime,
+                    available_runtimes
+                        .iter()
+                        .map(|runtime| runtime.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+
+            Error::UnsupportedTarget(target, available_targets) => {
+                write!(
+                    f,
+                    "Target '{}' is not available for this language, available targets: [{}]",
+                    target,
+                    available_targets
+                        .iter()
+                        .map(|target| target.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+
+            Error::TestsNotSupported() => {
+                write!(f, "Test mode is not supported for this language")
+            }
+
+            Error::ChecksNotSupported() => {
+                write!(f, "Check mode is not supported for this language")
+            }
+        }
+    }
+}
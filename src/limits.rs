@@ -0,0 +1,102 @@
+use crate::language::Language;
+use std::collections::HashMap;
+
+const DEFAULT_WALL_SECONDS: u64 = 15;
+const SLOW_WALL_SECONDS: u64 = 30;
+// Memory defaults only apply with a cgroup, where they limit resident memory
+const DEFAULT_MEMORY_BYTES: u64 = 512 * 1024 * 1024;
+const LARGE_MEMORY_BYTES: u64 = 1024 * 1024 * 1024;
+// The descriptors of a container are shared by all its processes
+const DEFAULT_NOFILE: u64 = 1024;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    pub wall_seconds: Option<u64>,
+    pub cpu_seconds: Option<u64>,
+    // Share of a cpu core in percent, only enforced with a cgroup
+    pub cpu_percent: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub fsize_bytes: Option<u64>,
+    pub nofile: Option<u64>,
+    pub nproc: Option<u64>,
+    pub output_bytes: Option<u64>,
+}
+
+impl Limits {
+    // Use the limits from other where this has no limit
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            wall_seconds: self.wall_seconds.or(other.wall_seconds),
+            cpu_seconds: self.cpu_seconds.or(other.cpu_seconds),
+            cpu_percent: self.cpu_percent.or(other.cpu_percent),
+            memory_bytes: self.memory_bytes.or(other.memory_bytes),
+            fsize_bytes: self.fsize_bytes.or(other.fsize_bytes),
+            nofile: self.nofile.or(other.nofile),
+            nproc: self.nproc.or(other.nproc),
+            output_bytes: self.output_bytes.or(other.output_bytes),
+        }
+    }
+}
+
+// Resolve limits for a language, limits given in the request takes precedence
+// over the limits from the config which takes precedence over the built-in defaults
+pub fn for_language(
+    language: &Language,
+    request_limits: Option<Limits>,
+    config_limits: &HashMap<Language, Limits>,
+    cgroup: bool,
+) -> Limits {
+    let config_limits = config_limits.get(language).copied().unwrap_or_default();
+
+    request_limits
+        .unwrap_or_default()
+        .or(config_limits)
+        .or(language_defaults(language, cgroup))
+        .or(common_defaults())
+}
+
+// Limits of requests with run instructions, only the common defaults apply
+pub fn for_request(request_limits: Option<Limits>) -> Limits {
+    request_limits.unwrap_or_default().or(common_defaults())
+}
+
+fn common_defaults() -> Limits {
+    Limits {
+        nofile: Some(DEFAULT_NOFILE),
+        ..Limits::default()
+    }
+}
+
+// Without a cgroup there is no default memory limit. memoryBytes limits virtual memory then,
+// and the JVM, .NET, V8, BEAM and most other runtimes reserve large amounts of it up front,
+// so they would fail to start. In a cgroup it limits resident memory of the process tree
+fn language_defaults(language: &Language, cgroup: bool) -> Limits {
+    let (wall_seconds, memory_bytes) = match language {
+        // Runtimes and compilers that are slow to start or compile and need more memory
+        Language::Ballerina
+        | Language::Carp
+        | Language::Clojure
+        | Language::Csharp
+        | Language::Eiffel
+        | Language::Elm
+        | Language::Fsharp
+        | Language::Groovy
+        | Language::Haskell
+        | Language::Idris
+        | Language::Java
+        | Language::Kotlin
+        | Language::Rust
+        | Language::Scala
+        | Language::Swift
+        | Language::TypeScript => (SLOW_WALL_SECONDS, LARGE_MEMORY_BYTES),
+
+        _ => (DEFAULT_WALL_SECONDS, DEFAULT_MEMORY_BYTES),
+    };
+
+    Limits {
+        wall_seconds: Some(wall_seconds),
+        memory_bytes: Some(memory_bytes).filter(|_| cgroup),
+        ..Limits::default()
+    }
+}
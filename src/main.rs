@@ -1,16 +1,60 @@
+mod artifact;
+mod bootstrap;
+mod cgroup;
 mod cmd;
+mod config;
+mod executable;
+mod glob;
+mod kernel;
 mod language;
+mod limits;
 mod non_empty_vec;
-
+mod shell;
+mod test_case;
+mod test_report;
+mod transcript;
+mod zmtp;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
 use language::RunInstructions;
+use std::cell::RefCell;
 use std::env;
 use std::fmt;
 use std::fs;
 use std::io;
+use std::os::unix;
+use std::os::unix::fs::PermissionsExt;
 use std::path;
 use std::path::Path;
 use std::process;
 use std::time;
+use unicode_normalization::UnicodeNormalization;
+
+// Directory in the work path with the home, temp and cache directories of the commands
+const ENV_DIR: &str = ".runner";
+
+// Where libfaketime is installed by the distribution packages
+const FAKETIME_LIBRARIES: &[&str] = &[
+    "/usr/lib/x86_64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/aarch64-linux-gnu/faketime/libfaketime.so.1",
+    "/usr/lib/faketime/libfaketime.so.1",
+];
+
+// Signals a crashed program was killed with
+const CRASH_SIGNALS: &[i32] = &[
+    libc::SIGSEGV,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGILL,
+];
+
+const MAX_BACKTRACE_BYTES: u64 = 64 * 1024;
+
+// Limits of most linux file systems
+const MAX_FILE_NAME_BYTES: usize = 255;
+const MAX_PATH_BYTES: usize = 1024;
 
 fn main() {
     let _ = start().map_err(handle_error);
@@ -20,7 +64,7 @@ fn handle_error(error: Error) {
     match error {
         // Print RunResult if it's a compile error
         Error::Compile(err) => {
-            let run_result = to_error_result(err);
+            let run_result = to_compile_error_result(err);
             let _ = serde_json::to_writer(io::stdout(), &run_result)
                 .map_err(Error::SerializeRunResult)
                 .map_err(handle_error);
@@ -36,57 +80,420 @@ fn handle_error(error: Error) {
 fn start() -> Result<(), Error> {
     let stdin = io::stdin();
     let stdout = io::stdout();
-    let args = env::args().collect();
+    let config = config::from_args(env::args().collect()).map_err(Error::ParseArgs)?;
+
+    if let Some(kernel_config) = config.kernel.clone() {
+        return start_kernel(&kernel_config, config);
+    }
+
+    if config.validate {
+        return print_validation(stdin, &config);
+    }
 
     let run_request = parse_request(stdin)?;
+    let run_result = run(run_request, &config)?;
+
+    serde_json::to_writer(stdout, &run_result).map_err(Error::SerializeRunResult)
+}
+
+// Print the plan of a valid request, or the error of an invalid request and exit with 1
+fn print_validation<R: io::Read>(stdin: R, config: &config::Config) -> Result<(), Error> {
+    let result = parse_request(stdin).and_then(|run_request| validate(run_request, config));
+
+    let validation = match result {
+        Ok(plan) => Validation {
+            valid: true,
+            error: None,
+            plan: Some(plan),
+        },
+
+        Err(err) => Validation {
+            valid: false,
+            error: Some(err.to_string()),
+            plan: None,
+        },
+    };
+
+    serde_json::to_writer(io::stdout(), &validation).map_err(Error::SerializeRunResult)?;
+
+    if !validation.valid {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+// Validate the request like a run would, without writing files or running commands
+fn validate(run_request: RunRequest, config: &config::Config) -> Result<RunPlan, Error> {
+    validate_stdin(run_request.stdin(), config)?;
+    validate_files(run_request.files(), config)?;
+    validate_test_cases(&run_request.options().test_cases, config)?;
+
+    let work_path = match &config.work_path {
+        Some(path) => path.to_path_buf(),
+
+        None => default_work_path(config)?,
+    };
+
+    command_work_path(&work_path, config)?;
+    parse_umask(run_request.options())?;
+
+    let limits = run_request.limits(config);
+    let build_timeout = run_request.options().build_timeout;
+    let run_timeout = run_request.options().run_timeout;
+    let setup_commands = run_request.options().setup_commands.clone();
+    let teardown_commands = [
+        run_request.options().teardown_commands.as_slice(),
+        config.teardown_commands.as_slice(),
+    ]
+    .concat();
+
+    let (files, run_instructions) = match run_request {
+        RunRequest::V1(mut run_request) => {
+            run_request.language_options.compile_daemon = config.compile_daemon;
+
+            let files = files_v1(&work_path, &mut run_request)?;
+            let run_instructions = run_instructions_v1(&work_path, &run_request, &files)?;
+            (files, run_instructions)
+        }
+
+        RunRequest::V2(run_request) => {
+            let files = run_request
+                .files
+                .into_iter()
+                .map(|file| file_from_request_file(&work_path, file))
+                .collect::<Result<Vec<_>, _>>()?;
 
-    let work_path = match work_path_from_args(args) {
-        Some(path) => path,
+            (files, run_request.run_instructions)
+        }
+    };
+
+    let file_names = files
+        .iter()
+        .map(|file| {
+            file.path
+                .strip_prefix(&work_path)
+                .map(|path| path.to_string_lossy().to_string())
+                .map_err(Error::StripWorkPath)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(RunPlan {
+        files: file_names,
+        setup_commands,
+        build_commands: run_instructions.build_commands,
+        run_command: run_instructions.run_command,
+        teardown_commands,
+        limits,
+        build_timeout,
+        run_timeout,
+    })
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Validation {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<RunPlan>,
+}
+
+// The commands a run would execute, in order
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RunPlan {
+    files: Vec<String>,
+    setup_commands: Vec<String>,
+    build_commands: Vec<String>,
+    run_command: String,
+    teardown_commands: Vec<String>,
+    limits: limits::Limits,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_timeout: Option<u64>,
+}
+
+// Run the code of each execute request as a language request with the code as the only file
+fn start_kernel(kernel_config: &config::KernelConfig, config: config::Config) -> Result<(), Error> {
+    let language = kernel_config.language.clone();
+    let file_name = kernel_config.file_name.clone();
+
+    kernel::start(kernel_config, move |code| {
+        let request = serde_json::json!({
+            "language": language,
+            "files": [{"name": file_name, "content": code}]
+        });
+
+        let result = serde_json::from_value(request)
+            .map_err(Error::ParseRequest)
+            .and_then(|run_request| run(run_request, &config));
+
+        let run_result = match result {
+            Ok(run_result) => run_result,
+            Err(Error::Compile(err)) => to_compile_error_result(err),
+            Err(err) => {
+                return kernel::Output {
+                    stdout: "".to_string(),
+                    stderr: "".to_string(),
+                    error: err.to_string(),
+                }
+            }
+        };
+
+        kernel::Output {
+            stdout: run_result.stdout,
+            stderr: run_result.stderr,
+            error: run_result.error,
+        }
+    })
+    .map_err(Error::Kernel)
+}
 
-        None => default_work_path()?,
+fn run(run_request: RunRequest, config: &config::Config) -> Result<RunResult, Error> {
+    validate_stdin(run_request.stdin(), config)?;
+    validate_files(run_request.files(), config)?;
+    validate_test_cases(&run_request.options().test_cases, config)?;
+
+    // An existing work path may contain files of the caller, only remove what the run created
+    let (work_path, created_work_path) = match &config.work_path {
+        Some(path) => (path.to_path_buf(), !path.exists()),
+
+        None => (create_default_work_path(config)?, true),
     };
+    let result = run_in_work_path(&work_path, run_request, config);
+
+    if config.keep_workdir {
+        let mut run_result = result?;
+
+        if let Some(meta) = &mut run_result.meta {
+            meta.work_path = Some(work_path.to_string_lossy().to_string());
+        }
+
+        return Ok(run_result);
+    }
+
+    if created_work_path {
+        // The result is more important than a failed cleanup
+        let _ = fs::remove_dir_all(&work_path);
+    }
+
+    result
+}
 
+fn run_in_work_path(
+    work_path: &Path,
+    run_request: RunRequest,
+    config: &config::Config,
+) -> Result<RunResult, Error> {
     // Some languages has a bootstrap file
     let bootstrap_file = Path::new("/bootstrap.tar.gz");
 
     if bootstrap_file.exists() {
-        unpack_bootstrap_file(&work_path, bootstrap_file)?;
+        match &config.bootstrap_cache {
+            Some(cache_path) => {
+                let cached_path = cached_bootstrap(cache_path, bootstrap_file)?;
+                bootstrap::copy_tree(&cached_path, work_path).map_err(Error::CopyBootstrap)?;
+            }
+
+            None => unpack_bootstrap_file(work_path, bootstrap_file)?,
+        }
     }
 
-    let run_result = match run_request {
-        RunRequest::V1(run_request) => run_v1(&work_path, run_request),
-        RunRequest::V2(run_request) => run_v2(&work_path, run_request),
-    }?;
+    let settings = CommandSettings {
+        limits: run_request.limits(config),
+        sandbox: config.sandbox,
+        env: [
+            isolated_env(work_path, &command_work_path(work_path, config)?)?,
+            locale_env(run_request.options()),
+            fake_time_env(run_request.options(), config),
+        ]
+        .concat(),
+        // The transcript is made from the output events
+        output_events: run_request.options().output_events || run_request.options().transcript,
+        close_stdin: run_request.options().close_stdin.unwrap_or(true),
+        umask: parse_umask(run_request.options())?,
+        backtrace: run_request.options().backtrace,
+        chroot: config.chroot.clone(),
+        term_grace_period: config.term_grace_period,
+        build_timeout: run_request.options().build_timeout,
+        run_timeout: run_request.options().run_timeout,
+        user: config.run_as_user,
+        cgroup: config.cgroup.clone(),
+        // The request can only take away network access, not give it back
+        isolate_network: config.no_network || run_request.options().network == Some(false),
+        executed_commands: RefCell::new(vec![]),
+    };
 
-    serde_json::to_writer(stdout, &run_result).map_err(Error::SerializeRunResult)
+    let teardown_commands = [
+        run_request.options().teardown_commands.as_slice(),
+        config.teardown_commands.as_slice(),
+    ]
+    .concat();
+
+    let output_events = run_request.options().output_events;
+
+    let result = match run_request {
+        RunRequest::V1(run_request) => run_v1(work_path, run_request, config, &settings),
+        RunRequest::V2(run_request) => run_v2(work_path, run_request, &settings),
+    };
+
+    // Teardown commands run even if the build failed, their failures don't fail the run
+    let teardown = teardown_commands
+        .iter()
+        .map(|command| {
+            let run_result = run_command(work_path, command, None, &settings);
+            to_command_result(command, run_result)
+        })
+        .collect();
+
+    let mut run_result = match result {
+        Ok((mut run_result, options)) => {
+            if options.include_teardown_output {
+                run_result.teardown = teardown;
+            }
+
+            // The output of the run is kept if the artifacts can't be collected
+            match artifact::collect(
+                work_path,
+                &options.artifacts,
+                &options.artifact_limits.unwrap_or_default(),
+            ) {
+                Ok(artifacts) => run_result.artifacts = artifacts,
+                Err(err) => run_result.artifact_error = Some(err.to_string()),
+            }
+
+            if options.transcript {
+                run_result
+                    .artifacts
+                    .push(to_transcript_artifact(&run_result));
+            }
+
+            run_result
+        }
+
+        Err(err) => return Err(err),
+    };
+
+    if !output_events {
+        run_result.events = vec![];
+    }
+
+    run_result.meta = Some(Meta {
+        work_path: None,
+        executed_commands: settings.executed_commands.take(),
+    });
+
+    Ok(run_result)
 }
 
-fn run_v1(work_path: &Path, run_request: RunRequestV1) -> Result<RunResult, Error> {
-    let files = run_request
-        .files
+fn run_v1(
+    work_path: &Path,
+    mut run_request: RunRequestV1,
+    config: &config::Config,
+    settings: &CommandSettings,
+) -> Result<(RunResult, RunOptions), Error> {
+    run_request.language_options.compile_daemon = config.compile_daemon;
+
+    let files = files_v1(work_path, &mut run_request)?;
+    let run_instructions = run_instructions_v1(work_path, &run_request, &files)?;
+
+    for file in &files {
+        write_file(file)?;
+    }
+
+    if let Some(user) = &settings.user {
+        chown_tree(work_path, user)?;
+    }
+
+    let setup = match run_setup_commands(work_path, &run_request.options.setup_commands, settings) {
+        Ok(setup) => setup,
+        Err(setup) => return Ok((to_setup_error_result(setup), run_request.options)),
+    };
+
+    let mut run_result =
+        match run_by_instructions(work_path, &run_instructions, run_request.stdin, settings) {
+            Ok(run_result) => run_result,
+            Err(Error::Compile(err)) => {
+                return Ok((to_build_error_result(err, setup), run_request.options))
+            }
+            Err(err) => return Err(err),
+        };
+
+    if run_request.mode == Some(Mode::Test) {
+        run_result.tests = test_report::parse(
+            &run_request.language,
+            &run_result.stdout,
+            &run_result.stderr,
+        );
+    }
+
+    run_result.test_cases = run_test_cases(
+        work_path,
+        &run_instructions.run_command,
+        &run_request.options.test_cases,
+        settings,
+    );
+    run_result.setup = setup;
+
+    Ok((run_result, run_request.options))
+}
+
+// The files of the request with the main file first
+fn files_v1(work_path: &Path, run_request: &mut RunRequestV1) -> Result<Vec<File>, Error> {
+    let mut files = std::mem::take(&mut run_request.files)
         .into_iter()
         .map(|file| file_from_request_file(work_path, file))
         .collect::<Result<Vec<_>, _>>()?;
 
-    for file in &files {
-        write_file(file)?;
+    if let Some(main_file) = &run_request.main {
+        move_main_file_first(&mut files, work_path, &main_file.nfc().collect::<String>())?;
     }
 
-    match run_request.command {
-        Some(command) if !command.is_empty() => {
-            let run_result = run_command(work_path, &command, run_request.stdin);
-            Ok(run_result)
-        }
+    Ok(files)
+}
 
-        Some(_) | None => {
-            let file_paths = get_relative_file_paths(work_path, files)?;
-            let run_instructions = language::run_instructions(&run_request.language, file_paths);
-            run_by_instructions(work_path, &run_instructions, run_request.stdin)
-        }
+fn run_instructions_v1(
+    work_path: &Path,
+    run_request: &RunRequestV1,
+    files: &[File],
+) -> Result<RunInstructions, Error> {
+    match &run_request.command {
+        Some(command) if !command.is_empty() => Ok(RunInstructions {
+            build_commands: vec![],
+            run_command: command.to_string(),
+        }),
+
+        Some(_) | None if run_request.mode == Some(Mode::Test) => language::test_instructions(
+            &run_request.language,
+            get_source_files(work_path, files)?,
+            &run_request.language_options,
+        )
+        .map_err(Error::Language),
+
+        Some(_) | None if run_request.mode == Some(Mode::Check) => language::check_instructions(
+            &run_request.language,
+            get_source_files(work_path, files)?,
+            &run_request.language_options,
+        )
+        .map_err(Error::Language),
+
+        Some(_) | None => language::run_instructions(
+            &run_request.language,
+            get_source_files(work_path, files)?,
+            &run_request.language_options,
+        )
+        .map_err(Error::Language),
     }
 }
 
-fn run_v2(work_path: &Path, run_request: RunRequestV2) -> Result<RunResult, Error> {
+fn run_v2(
+    work_path: &Path,
+    run_request: RunRequestV2,
+    settings: &CommandSettings,
+) -> Result<(RunResult, RunOptions), Error> {
     let files = run_request
         .files
         .into_iter()
@@ -97,7 +504,37 @@ fn run_v2(work_path: &Path, run_request: RunRequestV2) -> Result<RunResult, Erro
         write_file(file)?;
     }
 
-    run_by_instructions(work_path, &run_request.run_instructions, run_request.stdin)
+    if let Some(user) = &settings.user {
+        chown_tree(work_path, user)?;
+    }
+
+    let setup = match run_setup_commands(work_path, &run_request.options.setup_commands, settings) {
+        Ok(setup) => setup,
+        Err(setup) => return Ok((to_setup_error_result(setup), run_request.options)),
+    };
+
+    let mut run_result = match run_by_instructions(
+        work_path,
+        &run_request.run_instructions,
+        run_request.stdin,
+        settings,
+    ) {
+        Ok(run_result) => run_result,
+        Err(Error::Compile(err)) => {
+            return Ok((to_build_error_result(err, setup), run_request.options))
+        }
+        Err(err) => return Err(err),
+    };
+
+    run_result.test_cases = run_test_cases(
+        work_path,
+        &run_request.run_instructions.run_command,
+        &run_request.options.test_cases,
+        settings,
+    );
+    run_result.setup = setup;
+
+    Ok((run_result, run_request.options))
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -107,6 +544,166 @@ struct RunResult {
     stderr: String,
     error: String,
     duration: u64,
+    #[serde(flatten)]
+    truncation: Truncation,
+    #[serde(skip_serializing_if = "is_false")]
+    timed_out: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timed_out_phase: Option<Phase>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_memory_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_hint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    artifacts: Vec<artifact::Artifact>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tests: Vec<test_report::TestResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    test_cases: Vec<TestCaseResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    events: Vec<OutputEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backtrace: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    setup: Vec<CommandResult>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    teardown: Vec<CommandResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+// Output of a command that is not the main command of the run
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CommandResult {
+    command: String,
+    stdout: String,
+    stderr: String,
+    error: String,
+    duration: u64,
+}
+
+fn to_command_result(command: &str, run_result: RunResult) -> CommandResult {
+    CommandResult {
+        command: command.to_string(),
+        stdout: run_result.stdout,
+        stderr: run_result.stderr,
+        error: run_result.error,
+        duration: run_result.duration,
+    }
+}
+
+// Output of the run command for the stdin of a test case. The test case passed
+// if the command succeeded and its stdout matches the expected output
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TestCaseResult {
+    name: String,
+    status: test_report::Status,
+    stdout: String,
+    stderr: String,
+    error: String,
+    duration: u64,
+}
+
+fn to_test_case_result(name: String, passed: bool, run_result: RunResult) -> TestCaseResult {
+    TestCaseResult {
+        name,
+        status: if passed {
+            test_report::Status::Passed
+        } else {
+            test_report::Status::Failed
+        },
+        stdout: run_result.stdout,
+        stderr: run_result.stderr,
+        error: run_result.error,
+        duration: run_result.duration,
+    }
+}
+
+// Details about the run for reproducing it and debugging the runner itself.
+// The executed commands are listed in the order they were run
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    work_path: Option<String>,
+    executed_commands: Vec<String>,
+}
+
+// Set when stdout / stderr was truncated by the outputBytes limit,
+// with the number of bytes the command wrote
+#[derive(serde::Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Truncation {
+    #[serde(skip_serializing_if = "is_false")]
+    stdout_truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "is_false")]
+    stderr_truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stderr_bytes: Option<usize>,
+}
+
+fn to_truncation(truncation: cmd::Truncation) -> Truncation {
+    Truncation {
+        stdout_truncated: truncation.stdout_bytes.is_some(),
+        stdout_bytes: truncation.stdout_bytes,
+        stderr_truncated: truncation.stderr_bytes.is_some(),
+        stderr_bytes: truncation.stderr_bytes,
+    }
+}
+
+// The phase of the run a command belongs to, buildTimeout and runTimeout apply to their phase
+#[derive(serde::Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Phase {
+    Build,
+    Run,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+// Time is in nanoseconds since the command was started
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OutputEvent {
+    stream: cmd::Stream,
+    time: u64,
+    content: String,
+}
+
+// Recording of the output of the run command that can be replayed with asciinema
+fn to_transcript_artifact(run_result: &RunResult) -> artifact::Artifact {
+    let events: Vec<transcript::Event> = run_result
+        .events
+        .iter()
+        .map(|event| transcript::Event {
+            elapsed: time::Duration::from_nanos(event.time),
+            content: &event.content,
+        })
+        .collect();
+
+    let started = time::SystemTime::now() - time::Duration::from_nanos(run_result.duration);
+    let content = transcript::to_asciicast(&events, started);
+
+    artifact::to_artifact(transcript::FILE_NAME.to_string(), content.as_bytes())
+}
+
+fn to_output_events(events: &[cmd::OutputEvent]) -> Vec<OutputEvent> {
+    events
+        .iter()
+        .map(|event| OutputEvent {
+            stream: event.stream,
+            time: event.elapsed.as_nanos() as u64,
+            content: String::from_utf8_lossy(&event.content).to_string(),
+        })
+        .collect()
 }
 
 fn to_success_result(output: cmd::SuccessOutput) -> RunResult {
@@ -115,7 +712,76 @@ fn to_success_result(output: cmd::SuccessOutput) -> RunResult {
         stderr: output.stderr,
         error: "".to_string(),
         duration: output.duration.as_nanos() as u64,
+        truncation: to_truncation(output.truncation),
+        artifacts: vec![],
+        artifact_error: None,
+        tests: vec![],
+        test_cases: vec![],
+        timed_out: false,
+        timed_out_phase: None,
+        peak_memory_bytes: output.peak_memory_bytes,
+        limit_hint: None,
+        events: to_output_events(&output.events),
+        backtrace: None,
+        setup: vec![],
+        teardown: vec![],
+        meta: None,
+    }
+}
+
+// The last setup command is the one that failed
+fn to_setup_error_result(setup: Vec<CommandResult>) -> RunResult {
+    let (error, duration) = match setup.last() {
+        Some(failed) => (
+            format!(
+                "Setup command '{}' failed. {}",
+                failed.command, failed.error
+            ),
+            failed.duration,
+        ),
+
+        None => ("".to_string(), 0),
+    };
+
+    RunResult {
+        stdout: "".to_string(),
+        stderr: "".to_string(),
+        error,
+        duration,
+        truncation: Truncation::default(),
+        artifacts: vec![],
+        artifact_error: None,
+        tests: vec![],
+        test_cases: vec![],
+        timed_out: false,
+        timed_out_phase: None,
+        peak_memory_bytes: None,
+        limit_hint: None,
+        events: vec![],
+        backtrace: None,
+        setup,
+        teardown: vec![],
+        meta: None,
+    }
+}
+
+// Build failures are returned as a result, so teardown output, artifacts
+// and the executed commands are included like for any other run
+fn to_build_error_result(error: cmd::Error, setup: Vec<CommandResult>) -> RunResult {
+    RunResult {
+        setup,
+        ..to_compile_error_result(error)
+    }
+}
+
+fn to_compile_error_result(error: cmd::Error) -> RunResult {
+    let mut run_result = to_error_result(error);
+
+    if run_result.timed_out {
+        run_result.timed_out_phase = Some(Phase::Build);
     }
+
+    run_result
 }
 
 fn to_error_result(error: cmd::Error) -> RunResult {
@@ -123,14 +789,55 @@ fn to_error_result(error: cmd::Error) -> RunResult {
         cmd::Error::Output(cmd::OutputError::ExitFailure(output), duration) => RunResult {
             stdout: output.stdout,
             stderr: output.stderr,
-            error: match output.exit_code {
-                Some(exit_code) => {
+            error: match (output.exceeded_limit, output.exit_code) {
+                (Some(limit), Some(exit_code)) => {
+                    format!("{}. Exit code: {}", limit, exit_code)
+                }
+
+                (Some(limit), None) => limit.to_string(),
+
+                (None, Some(exit_code)) => {
                     format!("Exit code: {}", exit_code)
                 }
 
-                None => "".to_string(),
+                (None, None) => "".to_string(),
             },
             duration: duration.as_nanos() as u64,
+            truncation: to_truncation(output.truncation),
+            artifacts: vec![],
+            artifact_error: None,
+            tests: vec![],
+            test_cases: vec![],
+            timed_out: false,
+            timed_out_phase: None,
+            peak_memory_bytes: output.peak_memory_bytes,
+            limit_hint: output.limit_hint.map(|hint| hint.to_string()),
+            events: to_output_events(&output.events),
+            backtrace: None,
+            setup: vec![],
+            teardown: vec![],
+            meta: None,
+        },
+
+        cmd::Error::Timeout(ref output, _, duration) => RunResult {
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+            error: format!("{}", error),
+            duration: duration.as_nanos() as u64,
+            truncation: to_truncation(output.truncation),
+            artifacts: vec![],
+            artifact_error: None,
+            tests: vec![],
+            test_cases: vec![],
+            timed_out: true,
+            timed_out_phase: None,
+            peak_memory_bytes: output.peak_memory_bytes,
+            limit_hint: None,
+            events: to_output_events(&output.events),
+            backtrace: None,
+            setup: vec![],
+            teardown: vec![],
+            meta: None,
         },
 
         _ => RunResult {
@@ -138,6 +845,20 @@ fn to_error_result(error: cmd::Error) -> RunResult {
             stderr: "".to_string(),
             error: format!("{}", error),
             duration: error.duration().as_nanos() as u64,
+            truncation: Truncation::default(),
+            artifacts: vec![],
+            artifact_error: None,
+            tests: vec![],
+            test_cases: vec![],
+            timed_out: false,
+            timed_out_phase: None,
+            peak_memory_bytes: None,
+            limit_hint: None,
+            events: vec![],
+            backtrace: None,
+            setup: vec![],
+            teardown: vec![],
+            meta: None,
         },
     }
 }
@@ -149,13 +870,74 @@ enum RunRequest {
     V2(RunRequestV2),
 }
 
+impl RunRequest {
+    fn stdin(&self) -> Option<&cmd::Stdin> {
+        match self {
+            RunRequest::V1(run_request) => run_request.stdin.as_ref(),
+            RunRequest::V2(run_request) => run_request.stdin.as_ref(),
+        }
+    }
+
+    fn files(&self) -> &[RequestFile] {
+        match self {
+            RunRequest::V1(run_request) => &run_request.files,
+            RunRequest::V2(run_request) => &run_request.files,
+        }
+    }
+
+    fn options(&self) -> &RunOptions {
+        match self {
+            RunRequest::V1(run_request) => &run_request.options,
+            RunRequest::V2(run_request) => &run_request.options,
+        }
+    }
+
+    // Language requests fall back to the limits of the language. timeout, memoryLimitBytes,
+    // cpuTimeLimitSeconds and maxFileSizeBytes are shorthands for the limits
+    fn limits(&self, config: &config::Config) -> limits::Limits {
+        let limits = match self {
+            RunRequest::V1(run_request) => limits::for_language(
+                &run_request.language,
+                run_request.options.limits,
+                &config.language_limits,
+                config.cgroup.is_some(),
+            ),
+
+            RunRequest::V2(run_request) => limits::for_request(run_request.options.limits),
+        };
+
+        limits::Limits {
+            wall_seconds: self.options().timeout.or(limits.wall_seconds),
+            memory_bytes: self.options().memory_limit_bytes.or(limits.memory_bytes),
+            cpu_seconds: self.options().cpu_time_limit_seconds.or(limits.cpu_seconds),
+            fsize_bytes: self.options().max_file_size_bytes.or(limits.fsize_bytes),
+            ..limits
+        }
+    }
+}
+
 #[derive(serde::Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct RunRequestV1 {
     language: language::Language,
     files: Vec<RequestFile>,
-    stdin: Option<String>,
+    stdin: Option<cmd::Stdin>,
     command: Option<String>,
+    #[serde(alias = "entryPoint")]
+    main: Option<String>,
+    mode: Option<Mode>,
+    #[serde(flatten)]
+    language_options: language::Options,
+    #[serde(flatten)]
+    options: RunOptions,
+}
+
+#[derive(serde::Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    Run,
+    Test,
+    Check,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -163,7 +945,45 @@ struct RunRequestV1 {
 struct RunRequestV2 {
     run_instructions: RunInstructions,
     files: Vec<RequestFile>,
-    stdin: Option<String>,
+    stdin: Option<cmd::Stdin>,
+    #[serde(flatten)]
+    options: RunOptions,
+}
+
+// Options shared by all request versions
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RunOptions {
+    #[serde(default)]
+    artifacts: Vec<String>,
+    artifact_limits: Option<artifact::Limits>,
+    limits: Option<limits::Limits>,
+    timeout: Option<u64>,
+    memory_limit_bytes: Option<u64>,
+    cpu_time_limit_seconds: Option<u64>,
+    max_file_size_bytes: Option<u64>,
+    build_timeout: Option<u64>,
+    run_timeout: Option<u64>,
+    network: Option<bool>,
+    #[serde(default)]
+    output_events: bool,
+    close_stdin: Option<bool>,
+    umask: Option<String>,
+    tz: Option<String>,
+    locale: Option<String>,
+    fake_time: Option<u64>,
+    #[serde(default)]
+    backtrace: bool,
+    #[serde(default)]
+    setup_commands: Vec<String>,
+    #[serde(default)]
+    teardown_commands: Vec<String>,
+    #[serde(default)]
+    include_teardown_output: bool,
+    #[serde(default)]
+    transcript: bool,
+    #[serde(default)]
+    test_cases: Vec<test_case::TestCase>,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -171,59 +991,351 @@ struct RunRequestV2 {
 struct RequestFile {
     name: String,
     content: String,
+    encoding: Option<Encoding>,
+    charset: Option<Charset>,
+    mode: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    Utf8,
+    Base64,
+}
+
+#[derive(serde::Deserialize, Debug)]
+enum Charset {
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "latin-1", alias = "latin1", alias = "iso-8859-1")]
+    Latin1,
 }
 
 #[derive(Debug)]
 struct File {
     path: path::PathBuf,
-    content: String,
+    content: Vec<u8>,
+    mode: Option<u32>,
 }
 
 fn file_from_request_file(base_path: &path::Path, file: RequestFile) -> Result<File, Error> {
     err_if_false(!file.name.is_empty(), Error::EmptyFileName())?;
+
+    // Names are written as NFC so they match the names used in the sources
+    let name = file.name.nfc().collect::<String>();
     err_if_false(!file.content.is_empty(), Error::EmptyFileContent())?;
 
+    let mode = match &file.mode {
+        Some(mode) => Some(
+            parse_file_mode(mode)
+                .ok_or_else(|| Error::InvalidFileMode(file.name.clone(), mode.to_string()))?,
+        ),
+
+        None => None,
+    };
+
+    let content = match (&file.encoding, &file.charset) {
+        // Line breaks of wrapped base64 are allowed
+        (Some(Encoding::Base64), None) => BASE64_STANDARD
+            .decode(file.content.split_ascii_whitespace().collect::<String>())
+            .map_err(|err| Error::DecodeFileContent(file.name.clone(), err))?,
+
+        (Some(Encoding::Base64), Some(_)) => {
+            return Err(Error::CharsetWithBase64(file.name));
+        }
+
+        (_, Some(Charset::Latin1)) => encode_latin1(&file.content)
+            .map_err(|c| Error::EncodeFileContent(file.name.clone(), c))?,
+
+        (Some(Encoding::Utf8), _) | (None, _) => file.content.into_bytes(),
+    };
+
     Ok(File {
-        path: base_path.join(file.name),
-        content: file.content,
+        path: base_path.join(name),
+        content,
+        mode,
     })
 }
 
-fn parse_request<R: io::Read>(reader: R) -> Result<RunRequest, Error> {
-    serde_json::from_reader(reader).map_err(Error::ParseRequest)
+fn encode_latin1(content: &str) -> Result<Vec<u8>, char> {
+    content
+        .chars()
+        .map(|c| {
+            if (c as u32) <= 0xff {
+                Ok(c as u8)
+            } else {
+                Err(c)
+            }
+        })
+        .collect()
 }
 
-fn work_path_from_args(arguments: Vec<String>) -> Option<path::PathBuf> {
-    let args = arguments.iter().map(|s| s.as_ref()).collect::<Vec<&str>>();
+fn parse_file_mode(mode: &str) -> Option<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
 
-    match &args[1..] {
-        ["--path", path] => Some(path::PathBuf::from(path)),
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
 
-        _ => None,
-    }
+fn parse_umask(options: &RunOptions) -> Result<Option<u32>, Error> {
+    options
+        .umask
+        .as_ref()
+        .map(|umask| {
+            parse_file_mode(umask)
+                .filter(|umask| *umask <= 0o777)
+                .ok_or_else(|| Error::InvalidUmask(umask.to_string()))
+        })
+        .transpose()
 }
 
-fn default_work_path() -> Result<path::PathBuf, Error> {
-    let duration = time::SystemTime::now()
-        .duration_since(time::UNIX_EPOCH)
-        .map_err(Error::GetTimestamp)?;
+fn parse_request<R: io::Read>(reader: R) -> Result<RunRequest, Error> {
+    serde_json::from_reader(reader).map_err(Error::ParseRequest)
+}
 
-    let name = format!("glot-{}", duration.as_secs());
+fn validate_stdin(stdin: Option<&cmd::Stdin>, config: &config::Config) -> Result<(), Error> {
+    let length = stdin.map(|stdin| stdin.size()).unwrap_or(0);
 
-    Ok(env::temp_dir().join(name))
+    err_if_false(
+        length <= config.max_stdin_bytes,
+        Error::StdinTooLarge(length, config.max_stdin_bytes),
+    )
 }
 
-fn unpack_bootstrap_file(work_path: &path::Path, bootstrap_file: &path::Path) -> Result<(), Error> {
+fn validate_test_cases(
+    test_cases: &[test_case::TestCase],
+    config: &config::Config,
+) -> Result<(), Error> {
+    test_cases
+        .iter()
+        .enumerate()
+        .try_for_each(|(index, test_case)| {
+            validate_stdin(test_case.stdin.as_ref(), config)?;
+
+            test_case::validate(test_case)
+                .map_err(|err| Error::InvalidTestCase(test_case_name(index, test_case), err))
+        })
+}
+
+fn validate_files(files: &[RequestFile], config: &config::Config) -> Result<(), Error> {
+    err_if_false(
+        files.len() <= config.max_files,
+        Error::TooManyFiles(files.len(), config.max_files),
+    )?;
+
+    files.iter().try_for_each(|file| {
+        validate_file_name(&file.name)?;
+
+        let depth = path::Path::new(&file.name)
+            .components()
+            .count()
+            .saturating_sub(1);
+
+        err_if_false(
+            depth <= config.max_directory_depth,
+            Error::DirectoryTooDeep(file.name.clone(), config.max_directory_depth),
+        )
+    })
+}
+
+// Names are rejected if they would corrupt the generated shell commands
+// or be written outside of the work path
+fn validate_file_name(name: &str) -> Result<(), Error> {
+    if let Some(c) = name.chars().find(|c| c.is_control()) {
+        return Err(Error::InvalidFileNameCharacter(name.to_string(), c));
+    }
+
+    err_if_false(
+        name.len() <= MAX_PATH_BYTES,
+        Error::PathTooLong(name.to_string(), MAX_PATH_BYTES),
+    )?;
+
+    path::Path::new(name)
+        .components()
+        .try_for_each(|component| match component {
+            path::Component::Normal(part) => err_if_false(
+                part.len() <= MAX_FILE_NAME_BYTES,
+                Error::FileNameTooLong(name.to_string(), MAX_FILE_NAME_BYTES),
+            ),
+
+            _ => Err(Error::ReservedFileName(
+                name.to_string(),
+                component.as_os_str().to_string_lossy().to_string(),
+            )),
+        })
+}
+
+// The work path has to be inside the chroot directory to be visible to the commands
+fn default_work_path(config: &config::Config) -> Result<path::PathBuf, Error> {
+    let duration = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_err(Error::GetTimestamp)?;
+
+    let name = format!("glot-{}-{}", process::id(), duration.as_nanos());
+
+    let temp_dir = match &config.chroot {
+        Some(root) => root.join("tmp"),
+        None => env::temp_dir(),
+    };
+
+    Ok(temp_dir.join(name))
+}
+
+// Created exclusively, so concurrent runs never share a directory and remove it under each other
+fn create_default_work_path(config: &config::Config) -> Result<path::PathBuf, Error> {
+    let mut attempts = 0;
+
+    loop {
+        let work_path = default_work_path(config)?;
+
+        if let Some(parent_dir) = work_path.parent() {
+            fs::create_dir_all(parent_dir)
+                .map_err(|err| Error::CreateWorkPath(work_path.to_path_buf(), err))?;
+        }
+
+        attempts += 1;
+
+        match fs::create_dir(&work_path) {
+            Ok(()) => return Ok(work_path),
+
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => {}
+
+            Err(err) => return Err(Error::CreateWorkPath(work_path, err)),
+        }
+    }
+}
+
+// The files are written by the runner, the commands have to own them to write to the work path.
+// Symlinks are not followed
+fn chown_tree(file_path: &Path, user: &cmd::User) -> Result<(), Error> {
+    unix::fs::lchown(file_path, Some(user.uid), Some(user.gid))
+        .map_err(|err| Error::ChownWorkPath(file_path.to_path_buf(), err))?;
+
+    let metadata = fs::symlink_metadata(file_path)
+        .map_err(|err| Error::ChownWorkPath(file_path.to_path_buf(), err))?;
+
+    if metadata.is_dir() {
+        let entries = fs::read_dir(file_path)
+            .map_err(|err| Error::ChownWorkPath(file_path.to_path_buf(), err))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|err| Error::ChownWorkPath(file_path.to_path_buf(), err))?;
+            chown_tree(&entry.path(), user)?;
+        }
+    }
+
+    Ok(())
+}
+
+// The work path as seen by the commands
+fn command_work_path(work_path: &Path, config: &config::Config) -> Result<path::PathBuf, Error> {
+    match &config.chroot {
+        Some(root) => work_path
+            .strip_prefix(root)
+            .map(|relative_path| Path::new("/").join(relative_path))
+            .map_err(|_| Error::WorkPathOutsideChroot(work_path.to_path_buf(), root.to_path_buf())),
+
+        None => Ok(work_path.to_path_buf()),
+    }
+}
+
+fn unpack_bootstrap_file(work_path: &path::Path, bootstrap_file: &path::Path) -> Result<(), Error> {
+    let bootstrap_file_str = shell::quote(&bootstrap_file.to_string_lossy());
+
+    // Refuse archives with entries that would be written outside of the work path
+    let listing = cmd::run(cmd::Options {
+        work_path: work_path.to_path_buf(),
+        command: format!(
+            "tar --numeric-owner --quoting-style=c -tvzf {}",
+            bootstrap_file_str
+        ),
+        stdin: None,
+        close_stdin: true,
+        limits: limits::Limits::default(),
+        umask: None,
+        sandbox: false,
+        env: vec![],
+        output_events: false,
+        chroot: None,
+        term_grace_period: time::Duration::ZERO,
+        user: None,
+        cgroup: None,
+        isolate_network: false,
+    })
+    .map_err(Error::Bootstrap)?;
+
+    bootstrap::validate_listing(&listing.stdout).map_err(Error::UnsafeBootstrap)?;
+
     cmd::run(cmd::Options {
         work_path: work_path.to_path_buf(),
-        command: format!("tar -zxf {}", bootstrap_file.to_string_lossy()),
+        command: format!("tar -zxf {} --no-same-owner", bootstrap_file_str),
         stdin: None,
+        close_stdin: true,
+        limits: limits::Limits::default(),
+        umask: None,
+        sandbox: false,
+        env: vec![],
+        output_events: false,
+        chroot: None,
+        term_grace_period: time::Duration::ZERO,
+        user: None,
+        cgroup: None,
+        isolate_network: false,
     })
     .map_err(Error::Bootstrap)?;
 
     Ok(())
 }
 
+// Unpack the bootstrap file into the cache once. The directory is named by the size and
+// modification time of the file, so a replaced file is unpacked again. It's unpacked
+// into a temporary directory first, so concurrent runs never see a partial directory
+fn cached_bootstrap(
+    cache_path: &path::Path,
+    bootstrap_file: &path::Path,
+) -> Result<path::PathBuf, Error> {
+    let metadata = fs::metadata(bootstrap_file)
+        .map_err(|err| Error::BootstrapCache(bootstrap_file.to_path_buf(), err))?;
+
+    let modified = metadata
+        .modified()
+        .map_err(|err| Error::BootstrapCache(bootstrap_file.to_path_buf(), err))?
+        .duration_since(time::UNIX_EPOCH)
+        .map_err(Error::GetTimestamp)?;
+
+    let name = format!("{}-{}", metadata.len(), modified.as_nanos());
+    let cached_path = cache_path.join(&name);
+
+    if cached_path.exists() {
+        return Ok(cached_path);
+    }
+
+    let unpack_path = cache_path.join(format!(".{}-{}", name, process::id()));
+
+    fs::create_dir_all(&unpack_path)
+        .map_err(|err| Error::BootstrapCache(unpack_path.to_path_buf(), err))?;
+
+    let result = unpack_bootstrap_file(&unpack_path, bootstrap_file).and_then(|()| {
+        fs::rename(&unpack_path, &cached_path)
+            .or_else(|err| {
+                // Another run was faster
+                if cached_path.exists() {
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            })
+            .map_err(|err| Error::BootstrapCache(cached_path.to_path_buf(), err))
+    });
+
+    if unpack_path.exists() {
+        let _ = fs::remove_dir_all(&unpack_path);
+    }
+
+    result.map(|()| cached_path)
+}
+
 fn write_file(file: &File) -> Result<(), Error> {
     let parent_dir = file
         .path
@@ -235,37 +1347,213 @@ fn write_file(file: &File) -> Result<(), Error> {
         .map_err(|err| Error::CreateParentDir(parent_dir.to_path_buf(), err))?;
 
     fs::write(&file.path, &file.content)
-        .map_err(|err| Error::WriteFile(file.path.to_path_buf(), err))
+        .map_err(|err| Error::WriteFile(file.path.to_path_buf(), err))?;
+
+    if let Some(mode) = file.mode {
+        fs::set_permissions(&file.path, fs::Permissions::from_mode(mode))
+            .map_err(|err| Error::SetFilePermissions(file.path.to_path_buf(), err))?;
+    }
+
+    Ok(())
+}
+
+// Settings shared by all commands of a run
+struct CommandSettings {
+    limits: limits::Limits,
+    sandbox: bool,
+    env: Vec<(String, String)>,
+    output_events: bool,
+    close_stdin: bool,
+    umask: Option<u32>,
+    backtrace: bool,
+    chroot: Option<path::PathBuf>,
+    term_grace_period: time::Duration,
+    build_timeout: Option<u64>,
+    run_timeout: Option<u64>,
+    user: Option<cmd::User>,
+    cgroup: Option<path::PathBuf>,
+    isolate_network: bool,
+    executed_commands: RefCell<Vec<String>>,
+}
+
+// Point HOME, TMPDIR and the XDG directories into the work path, so toolchains
+// don't write caches and config into shared locations of the container
+fn isolated_env(
+    work_path: &path::Path,
+    command_work_path: &path::Path,
+) -> Result<Vec<(String, String)>, Error> {
+    let env_path = work_path.join(ENV_DIR);
+    let command_env_path = command_work_path.join(ENV_DIR);
+
+    let dirs = [
+        ("HOME", "home"),
+        ("TMPDIR", "tmp"),
+        ("XDG_CACHE_HOME", "cache"),
+        ("XDG_CONFIG_HOME", "config"),
+        ("XDG_DATA_HOME", "data"),
+        ("XDG_STATE_HOME", "state"),
+    ];
+
+    dirs.iter()
+        .map(|(name, dir_name)| {
+            let dir_path = env_path.join(dir_name);
+
+            fs::create_dir_all(&dir_path)
+                .map_err(|err| Error::CreateEnvDir(dir_path.to_path_buf(), err))?;
+
+            let command_dir_path = command_env_path.join(dir_name);
+            Ok((
+                name.to_string(),
+                command_dir_path.to_string_lossy().to_string(),
+            ))
+        })
+        .collect()
+}
+
+// Timezone and locale of the commands, so formatted dates and numbers don't depend on the image
+fn locale_env(options: &RunOptions) -> Vec<(String, String)> {
+    let mut env = vec![];
+
+    if let Some(tz) = &options.tz {
+        env.push(("TZ".to_string(), tz.to_string()));
+    }
+
+    if let Some(locale) = &options.locale {
+        env.push(("LANG".to_string(), locale.to_string()));
+        env.push(("LC_ALL".to_string(), locale.to_string()));
+    }
+
+    env
 }
 
-fn compile(work_path: &path::Path, command: &str) -> Result<cmd::SuccessOutput, Error> {
+// Pin the clock of the commands to the given unix time. SOURCE_DATE_EPOCH is respected by
+// build tools, libfaketime makes the clock start at the time when each command starts.
+// The monotonic clock is not faked, so timeouts and sleeps still work
+fn fake_time_env(options: &RunOptions, config: &config::Config) -> Vec<(String, String)> {
+    let seconds = match options.fake_time {
+        Some(seconds) => seconds,
+        None => return vec![],
+    };
+
+    let mut env = vec![("SOURCE_DATE_EPOCH".to_string(), seconds.to_string())];
+
+    let library = config.faketime_library.clone().or_else(|| {
+        FAKETIME_LIBRARIES
+            .iter()
+            .map(path::PathBuf::from)
+            .find(|library| library.exists())
+    });
+
+    if let Some(library) = library {
+        env.push((
+            "LD_PRELOAD".to_string(),
+            library.to_string_lossy().to_string(),
+        ));
+        env.push(("FAKETIME".to_string(), format!("@{}", seconds)));
+        // Parsed as unix time, so the time is not shifted by TZ
+        env.push(("FAKETIME_FMT".to_string(), "%s".to_string()));
+        env.push(("FAKETIME_DONT_FAKE_MONOTONIC".to_string(), "1".to_string()));
+    }
+
+    env
+}
+
+fn compile(
+    work_path: &path::Path,
+    command: &str,
+    settings: &CommandSettings,
+) -> Result<cmd::SuccessOutput, Error> {
     cmd::run(cmd::Options {
-        work_path: work_path.to_path_buf(),
-        command: command.to_string(),
-        stdin: None,
+        close_stdin: true,
+        ..phase_options(work_path, command, None, settings, Phase::Build)
     })
     .map_err(Error::Compile)
 }
 
+// Run the setup commands until one fails, the results so far are returned as the error
+fn run_setup_commands(
+    work_path: &Path,
+    commands: &[String],
+    settings: &CommandSettings,
+) -> Result<Vec<CommandResult>, Vec<CommandResult>> {
+    let mut setup = vec![];
+
+    for command in commands {
+        let command_result =
+            to_command_result(command, run_command(work_path, command, None, settings));
+        let failed = !command_result.error.is_empty();
+        setup.push(command_result);
+
+        if failed {
+            return Err(setup);
+        }
+    }
+
+    Ok(setup)
+}
+
+// Run the already built program once per test case
+fn run_test_cases(
+    work_path: &Path,
+    command: &str,
+    test_cases: &[test_case::TestCase],
+    settings: &CommandSettings,
+) -> Vec<TestCaseResult> {
+    test_cases
+        .iter()
+        .enumerate()
+        .map(|(index, test_case)| {
+            let options = phase_options(
+                work_path,
+                command,
+                test_case.stdin.clone(),
+                settings,
+                Phase::Run,
+            );
+
+            let run_result = match cmd::run(options) {
+                Ok(output) => to_success_result(output),
+
+                Err(err) => to_error_result(err),
+            };
+
+            let passed =
+                run_result.error.is_empty() && test_case::matches(test_case, &run_result.stdout);
+
+            to_test_case_result(test_case_name(index, test_case), passed, run_result)
+        })
+        .collect()
+}
+
+// Test cases without a name are numbered from 1
+fn test_case_name(index: usize, test_case: &test_case::TestCase) -> String {
+    match &test_case.name {
+        Some(name) => name.to_string(),
+        None => (index + 1).to_string(),
+    }
+}
+
 fn run_by_instructions(
     work_path: &Path,
     run_instructions: &RunInstructions,
-    stdin: Option<String>,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
 ) -> Result<RunResult, Error> {
     for command in &run_instructions.build_commands {
-        compile(work_path, command)?;
+        compile(work_path, command, settings)?;
     }
 
-    let run_result = run_command(work_path, &run_instructions.run_command, stdin);
+    let run_result = run_main_command(work_path, &run_instructions.run_command, stdin, settings);
     Ok(run_result)
 }
 
-fn run_command(work_path: &path::Path, command: &str, stdin: Option<String>) -> RunResult {
-    let result = cmd::run(cmd::Options {
-        work_path: work_path.to_path_buf(),
-        command: command.to_string(),
-        stdin,
-    });
+fn run_command(
+    work_path: &path::Path,
+    command: &str,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
+) -> RunResult {
+    let result = cmd::run(command_options(work_path, command, stdin, settings));
 
     match result {
         Ok(output) => to_success_result(output),
@@ -274,51 +1562,298 @@ fn run_command(work_path: &path::Path, command: &str, stdin: Option<String>) ->
     }
 }
 
-fn get_relative_file_paths(
+// The command that runs the program, if it crashed and backtraces are enabled
+// it's run again under gdb to get a backtrace
+fn run_main_command(
     work_path: &path::Path,
-    files: Vec<File>,
-) -> Result<non_empty_vec::NonEmptyVec<path::PathBuf>, Error> {
-    let names = files
-        .into_iter()
+    command: &str,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
+) -> RunResult {
+    let result = cmd::run(phase_options(
+        work_path,
+        command,
+        stdin.clone(),
+        settings,
+        Phase::Run,
+    ));
+
+    let crashed = match &result {
+        Err(cmd::Error::Output(cmd::OutputError::ExitFailure(output), _)) => output
+            .signal
+            .is_some_and(|signal| CRASH_SIGNALS.contains(&signal)),
+
+        _ => false,
+    };
+
+    let mut run_result = match result {
+        Ok(output) => to_success_result(output),
+
+        Err(err) => to_error_result(err),
+    };
+
+    if run_result.timed_out {
+        run_result.timed_out_phase = Some(Phase::Run);
+    }
+
+    if settings.backtrace && crashed {
+        run_result.backtrace = get_backtrace(work_path, command, stdin, settings);
+    }
+
+    run_result
+}
+
+// Run the command under gdb with the output of the program discarded,
+// gdb follows the fork of the shell into the program
+fn get_backtrace(
+    work_path: &path::Path,
+    command: &str,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
+) -> Option<String> {
+    let program_command = format!("exec >/dev/null 2>&1; {}", command);
+
+    let gdb_command = format!(
+        "gdb -batch -nx -ex 'set follow-fork-mode child' -ex run -ex 'bt full' --args sh -c {}",
+        shell::quote(&program_command)
+    );
+
+    let options = cmd::Options {
+        limits: limits::Limits {
+            output_bytes: Some(MAX_BACKTRACE_BYTES),
+            ..settings.limits
+        },
+        output_events: false,
+        ..command_options(work_path, &gdb_command, stdin, settings)
+    };
+
+    let stdout = match cmd::run(options) {
+        Ok(output) => output.stdout,
+        Err(cmd::Error::Output(cmd::OutputError::ExitFailure(output), _)) => output.stdout,
+        Err(_) => return None,
+    };
+
+    Some(stdout).filter(|stdout| !stdout.is_empty())
+}
+
+// Options for running a command, the command is recorded as executed
+fn command_options(
+    work_path: &path::Path,
+    command: &str,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
+) -> cmd::Options {
+    settings
+        .executed_commands
+        .borrow_mut()
+        .push(command.to_string());
+
+    cmd::Options {
+        work_path: work_path.to_path_buf(),
+        command: command.to_string(),
+        stdin,
+        close_stdin: settings.close_stdin,
+        limits: settings.limits,
+        umask: settings.umask,
+        sandbox: settings.sandbox,
+        env: settings.env.clone(),
+        output_events: settings.output_events,
+        chroot: settings.chroot.clone(),
+        term_grace_period: settings.term_grace_period,
+        user: settings.user,
+        cgroup: settings.cgroup.clone(),
+        isolate_network: settings.isolate_network,
+    }
+}
+
+// Options for a build command or the run command, the timeout of the phase
+// replaces the wall time limit
+fn phase_options(
+    work_path: &path::Path,
+    command: &str,
+    stdin: Option<cmd::Stdin>,
+    settings: &CommandSettings,
+    phase: Phase,
+) -> cmd::Options {
+    let timeout = match phase {
+        Phase::Build => settings.build_timeout,
+        Phase::Run => settings.run_timeout,
+    };
+
+    cmd::Options {
+        limits: limits::Limits {
+            wall_seconds: timeout.or(settings.limits.wall_seconds),
+            ..settings.limits
+        },
+        ..command_options(work_path, command, stdin, settings)
+    }
+}
+
+// The first file is used as the main file when generating run instructions
+fn move_main_file_first(
+    files: &mut Vec<File>,
+    work_path: &path::Path,
+    main_file_name: &str,
+) -> Result<(), Error> {
+    let main_file_path = work_path.join(main_file_name);
+
+    let index = files
+        .iter()
+        .position(|file| file.path == main_file_path)
+        .ok_or_else(|| Error::MainFileNotFound(main_file_name.to_string()))?;
+
+    let main_file = files.remove(index);
+    files.insert(0, main_file);
+
+    Ok(())
+}
+
+fn get_source_files(
+    work_path: &path::Path,
+    files: &[File],
+) -> Result<non_empty_vec::NonEmptyVec<language::SourceFile>, Error> {
+    let source_files = files
+        .iter()
         .map(|file| {
             let path = file
                 .path
                 .strip_prefix(work_path)
                 .map_err(Error::StripWorkPath)?;
 
-            Ok(path.to_path_buf())
+            Ok(language::SourceFile {
+                path: path.to_path_buf(),
+                content: String::from_utf8_lossy(&file.content).to_string(),
+            })
         })
-        .collect::<Result<Vec<path::PathBuf>, Error>>()?;
+        .collect::<Result<Vec<language::SourceFile>, Error>>()?;
 
-    non_empty_vec::from_vec(names).ok_or(Error::NoFiles())
+    non_empty_vec::from_vec(source_files).ok_or(Error::NoFiles())
 }
 
 enum Error {
+    ParseArgs(config::Error),
     ParseRequest(serde_json::Error),
+    StdinTooLarge(usize, usize),
+    TooManyFiles(usize, usize),
+    DirectoryTooDeep(String, usize),
+    InvalidFileNameCharacter(String, char),
+    ReservedFileName(String, String),
+    FileNameTooLong(String, usize),
+    PathTooLong(String, usize),
     NoFiles(),
+    MainFileNotFound(String),
+    Language(language::Error),
     StripWorkPath(path::StripPrefixError),
     EmptyFileName(),
     EmptyFileContent(),
+    InvalidFileMode(String, String),
+    InvalidUmask(String),
+    InvalidTestCase(String, test_case::Error),
+    DecodeFileContent(String, base64::DecodeError),
+    CharsetWithBase64(String),
+    EncodeFileContent(String, char),
     GetTimestamp(time::SystemTimeError),
+    CreateWorkPath(path::PathBuf, io::Error),
     GetParentDir(path::PathBuf),
     CreateParentDir(path::PathBuf, io::Error),
+    CreateEnvDir(path::PathBuf, io::Error),
+    WorkPathOutsideChroot(path::PathBuf, path::PathBuf),
     WriteFile(path::PathBuf, io::Error),
+    SetFilePermissions(path::PathBuf, io::Error),
+    ChownWorkPath(path::PathBuf, io::Error),
     Bootstrap(cmd::Error),
+    UnsafeBootstrap(bootstrap::Error),
+    BootstrapCache(path::PathBuf, io::Error),
+    CopyBootstrap(bootstrap::Error),
     Compile(cmd::Error),
     SerializeRunResult(serde_json::Error),
+    Kernel(kernel::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Error::ParseArgs(err) => {
+                write!(f, "Failed to parse arguments, {}", err)
+            }
+
             Error::ParseRequest(err) => {
                 write!(f, "Failed to parse request json, {}", err)
             }
 
+            Error::StdinTooLarge(length, max_length) => {
+                write!(
+                    f,
+                    "Error, stdin is {} bytes which exceeds the limit of {} bytes",
+                    length, max_length
+                )
+            }
+
+            Error::TooManyFiles(count, max_count) => {
+                write!(
+                    f,
+                    "Error, {} files were given which exceeds the limit of {} files",
+                    count, max_count
+                )
+            }
+
+            Error::DirectoryTooDeep(name, max_depth) => {
+                write!(
+                    f,
+                    "Error, file '{}' is nested deeper than {} directories",
+                    name, max_depth
+                )
+            }
+
+            Error::InvalidFileNameCharacter(name, c) => {
+                write!(
+                    f,
+                    "Error, file name '{}' contains the invalid character '{}'",
+                    name.escape_debug(),
+                    c.escape_debug()
+                )
+            }
+
+            Error::ReservedFileName(name, component) => {
+                write!(
+                    f,
+                    "Error, file name '{}' contains the reserved path component '{}'",
+                    name, component
+                )
+            }
+
+            Error::FileNameTooLong(name, max_bytes) => {
+                write!(
+                    f,
+                    "Error, file name '{}' has a path component longer than {} bytes",
+                    name, max_bytes
+                )
+            }
+
+            Error::PathTooLong(name, max_bytes) => {
+                write!(
+                    f,
+                    "Error, file name '{}' is longer than {} bytes",
+                    name, max_bytes
+                )
+            }
+
             Error::NoFiles() => {
                 write!(f, "Error, no files were given")
             }
 
+            Error::MainFileNotFound(name) => {
+                write!(
+                    f,
+                    "Error, main file '{}' is not among the given files",
+                    name
+                )
+            }
+
+            Error::Language(err) => {
+                write!(f, "Failed to get run instructions, {}", err)
+            }
+
             Error::StripWorkPath(err) => {
                 write!(f, "Failed to strip work path of file. {}", err)
             }
@@ -331,10 +1866,55 @@ impl fmt::Display for Error {
                 write!(f, "Error, file with empty content")
             }
 
+            Error::InvalidFileMode(name, mode) => {
+                write!(f, "Error, file '{}' has invalid mode: '{}'", name, mode)
+            }
+
+            Error::InvalidUmask(umask) => {
+                write!(f, "Error, invalid umask: '{}'", umask)
+            }
+
+            Error::InvalidTestCase(name, err) => {
+                write!(f, "Error, test case '{}' is invalid. {}", name, err)
+            }
+
+            Error::DecodeFileContent(name, err) => {
+                write!(
+                    f,
+                    "Failed to decode base64 content of file '{}'. {}",
+                    name, err
+                )
+            }
+
+            Error::CharsetWithBase64(name) => {
+                write!(
+                    f,
+                    "Error, file '{}' can't have both a charset and base64 encoding",
+                    name
+                )
+            }
+
+            Error::EncodeFileContent(name, c) => {
+                write!(
+                    f,
+                    "Failed to encode content of file '{}', character '{}' is not in the charset",
+                    name, c
+                )
+            }
+
             Error::GetTimestamp(err) => {
                 write!(f, "Failed to get timestamp for work directory, {}", err)
             }
 
+            Error::CreateWorkPath(work_path, err) => {
+                write!(
+                    f,
+                    "Failed to create work directory '{}'. {}",
+                    work_path.to_string_lossy(),
+                    err
+                )
+            }
+
             Error::GetParentDir(file_path) => {
                 write!(
                     f,
@@ -352,6 +1932,24 @@ impl fmt::Display for Error {
                 )
             }
 
+            Error::WorkPathOutsideChroot(work_path, root) => {
+                write!(
+                    f,
+                    "Error, the work path '{}' is not inside the chroot directory '{}'",
+                    work_path.to_string_lossy(),
+                    root.to_string_lossy()
+                )
+            }
+
+            Error::CreateEnvDir(dir_path, err) => {
+                write!(
+                    f,
+                    "Failed to create directory '{}'. {}",
+                    dir_path.to_string_lossy(),
+                    err
+                )
+            }
+
             Error::WriteFile(file_path, err) => {
                 write!(
                     f,
@@ -361,10 +1959,45 @@ impl fmt::Display for Error {
                 )
             }
 
+            Error::SetFilePermissions(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to set permissions on file: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::ChownWorkPath(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to change the owner of: '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
             Error::Bootstrap(err) => {
                 write!(f, "Failed to unpack bootstrap file: {}", err)
             }
 
+            Error::UnsafeBootstrap(err) => {
+                write!(f, "Refusing to unpack bootstrap file. {}", err)
+            }
+
+            Error::BootstrapCache(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to cache bootstrap file at '{}'. {}",
+                    file_path.to_string_lossy(),
+                    err
+                )
+            }
+
+            Error::CopyBootstrap(err) => {
+                write!(f, "Failed to copy cached bootstrap files. {}", err)
+            }
+
             Error::Compile(err) => {
                 write!(f, "Failed to compile: {}", err)
             }
@@ -372,6 +2005,10 @@ impl fmt::Display for Error {
             Error::SerializeRunResult(err) => {
                 write!(f, "Failed to serialize run result: {}", err)
             }
+
+            Error::Kernel(err) => {
+                write!(f, "Kernel failed: {}", err)
+            }
         }
     }
 }
@@ -0,0 +1,13 @@
+// Quote a string so it's passed as a single word to `sh -c`.
+// Strings that only contain safe characters are returned as is
+pub fn quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(is_safe_char) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+fn is_safe_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c)
+}
@@ -0,0 +1,163 @@
+use crate::cmd;
+use std::fmt;
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TestCase {
+    pub name: Option<String>,
+    pub stdin: Option<cmd::Stdin>,
+    pub expected_output: String,
+    #[serde(default)]
+    pub comparison: Comparison,
+}
+
+// How the stdout of the program is compared to the expected output, exact by default
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Comparison {
+    #[serde(default)]
+    pub trim_trailing_whitespace: bool,
+    #[serde(default)]
+    pub normalize_line_endings: bool,
+    pub numeric_tolerance: Option<f64>,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+pub fn validate(test_case: &TestCase) -> Result<(), Error> {
+    if let Some(tolerance) = test_case.comparison.numeric_tolerance {
+        if !tolerance.is_finite() || tolerance < 0.0 {
+            return Err(Error::InvalidTolerance(tolerance));
+        }
+    }
+
+    if test_case.comparison.regex {
+        to_regex(&test_case.expected_output).map_err(Error::InvalidRegex)?;
+    }
+
+    Ok(())
+}
+
+// The output is normalized first, then matched against the expected output
+// as a regex, token by token with the numeric tolerance, or exactly
+pub fn matches(test_case: &TestCase, output: &str) -> bool {
+    let comparison = &test_case.comparison;
+    let output = normalize(output, comparison);
+
+    if comparison.regex {
+        return to_regex(&test_case.expected_output)
+            .map(|regex| regex.is_match(&output))
+            .unwrap_or(false);
+    }
+
+    let expected = normalize(&test_case.expected_output, comparison);
+
+    match comparison.numeric_tolerance {
+        Some(tolerance) => tokens_match(&expected, &output, tolerance),
+        None => expected == output,
+    }
+}
+
+fn normalize(text: &str, comparison: &Comparison) -> String {
+    let mut text = text.to_string();
+
+    if comparison.normalize_line_endings {
+        text = text.replace("\r\n", "\n").replace('\r', "\n");
+    }
+
+    if comparison.trim_trailing_whitespace {
+        text = text
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string();
+    }
+
+    text
+}
+
+// The whole output has to match, not just a part of it
+fn to_regex(pattern: &str) -> Result<regex_lite::Regex, regex_lite::Error> {
+    regex_lite::Regex::new(&format!("^(?:{})$", pattern))
+}
+
+// Whitespace separated tokens, numbers are equal if they differ by at most the tolerance
+fn tokens_match(expected: &str, output: &str, tolerance: f64) -> bool {
+    let expected_tokens: Vec<&str> = expected.split_whitespace().collect();
+    let output_tokens: Vec<&str> = output.split_whitespace().collect();
+
+    expected_tokens.len() == output_tokens.len()
+        && expected_tokens
+            .iter()
+            .zip(output_tokens.iter())
+            .all(|(expected, output)| token_matches(expected, output, tolerance))
+}
+
+fn token_matches(expected: &str, output: &str, tolerance: f64) -> bool {
+    if expected == output {
+        return true;
+    }
+
+    match (expected.parse::<f64>(), output.parse::<f64>()) {
+        (Ok(expected), Ok(output)) => (expected - output).abs() <= tolerance,
+        _ => false,
+    }
+}
+
+pub enum Error {
+    InvalidTolerance(f64),
+    InvalidRegex(regex_lite::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidTolerance(tolerance) => {
+                write!(
+                    f,
+                    "Numeric tolerance must be a finite, non-negative number: {}",
+                    tolerance
+                )
+            }
+
+            Error::InvalidRegex(err) => {
+                write!(f, "Invalid regex: {}", err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_tolerance(tolerance: f64) -> TestCase {
+        TestCase {
+            name: None,
+            stdin: None,
+            expected_output: "1.0".to_string(),
+            comparison: Comparison {
+                numeric_tolerance: Some(tolerance),
+                ..Comparison::default()
+            },
+        }
+    }
+
+    #[test]
+    fn valid_tolerance() {
+        assert!(validate(&with_tolerance(0.0)).is_ok());
+        assert!(validate(&with_tolerance(0.001)).is_ok());
+    }
+
+    #[test]
+    fn invalid_tolerance() {
+        for tolerance in [-0.1, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                validate(&with_tolerance(tolerance)),
+                Err(Error::InvalidTolerance(_))
+            ));
+        }
+    }
+}
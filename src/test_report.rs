@@ -0,0 +1,236 @@
+use crate::language::Language;
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResult {
+    pub name: String,
+    pub status: Status,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+// Parse the per-test results from the output of the test runner of the language.
+// Some runners, i.e. jest, report on stderr, so stdout is followed by stderr.
+// Lines that are not recognized are ignored
+pub fn parse(language: &Language, stdout: &str, stderr: &str) -> Vec<TestResult> {
+    let mut results = parse_output(language, stdout);
+    results.extend(parse_output(language, stderr));
+    results
+}
+
+fn parse_output(language: &Language, output: &str) -> Vec<TestResult> {
+    match language {
+        Language::Elixir => parse_exunit(output),
+        Language::Go => parse_go_test(output),
+        Language::JavaScript => {
+            let mut results = parse_tap(output);
+            results.extend(parse_jest(output));
+            results
+        }
+        Language::Python => parse_pytest(output),
+        Language::Rust => parse_libtest(output),
+        _ => vec![],
+    }
+}
+
+// pytest -v: `test_main.py::test_add PASSED   [ 50%]`
+fn parse_pytest(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let name = words.next().filter(|name| name.contains("::"))?;
+
+            let status = match words.next()? {
+                "PASSED" | "XPASS" => Status::Passed,
+                "FAILED" | "ERROR" => Status::Failed,
+                "SKIPPED" | "XFAIL" => Status::Skipped,
+                _ => return None,
+            };
+
+            Some(to_test_result(name, status))
+        })
+        .collect()
+}
+
+// Rust test harness: `test tests::add ... ok`
+fn parse_libtest(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, result) = line.strip_prefix("test ")?.split_once(" ... ")?;
+
+            let status = match result.trim() {
+                "ok" => Status::Passed,
+                "FAILED" => Status::Failed,
+                result if result.starts_with("ignored") => Status::Skipped,
+                _ => return None,
+            };
+
+            Some(to_test_result(name, status))
+        })
+        .collect()
+}
+
+// go test -v: `--- PASS: TestAdd (0.00s)`, subtests are indented
+fn parse_go_test(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (result, rest) = line.trim_start().strip_prefix("--- ")?.split_once(": ")?;
+
+            let status = match result {
+                "PASS" => Status::Passed,
+                "FAIL" => Status::Failed,
+                "SKIP" => Status::Skipped,
+                _ => return None,
+            };
+
+            let name = rest.split_whitespace().next()?;
+            Some(to_test_result(name, status))
+        })
+        .collect()
+}
+
+// TAP, as printed by `node --test`: `ok 1 - adds`, `not ok 2 - subtracts # SKIP`
+fn parse_tap(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+
+            let (passed, rest) = match line.strip_prefix("not ok ") {
+                Some(rest) => (false, rest),
+                None => (true, line.strip_prefix("ok ")?),
+            };
+
+            let description = rest
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .trim_start()
+                .trim_start_matches("- ");
+
+            let (name, directive) = match description.split_once(" # ") {
+                Some((name, directive)) => (name, directive.to_uppercase()),
+                None => (description, String::new()),
+            };
+
+            let status = if directive.starts_with("SKIP") || directive.starts_with("TODO") {
+                Status::Skipped
+            } else if passed {
+                Status::Passed
+            } else {
+                Status::Failed
+            };
+
+            Some(to_test_result(name.trim(), status))
+        })
+        .collect()
+}
+
+// mix test --trace: `  * test adds (0.01ms) [L#4]`. Failures are listed afterwards
+// as `  1) test adds (MathTest)`
+fn parse_exunit(output: &str) -> Vec<TestResult> {
+    let mut results: Vec<TestResult> = output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim_start().strip_prefix("* test ")?;
+            let rest = rest.split(" [L#").next()?;
+            let (name, details) = rest.rsplit_once(" (")?;
+
+            let status = if details.starts_with("skipped") || details.starts_with("excluded") {
+                Status::Skipped
+            } else {
+                Status::Passed
+            };
+
+            Some(to_test_result(name, status))
+        })
+        .collect();
+
+    for line in output.lines() {
+        let failed_name = line
+            .trim_start()
+            .split_once(") test ")
+            .filter(|(number, _)| number.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|(_, rest)| rest.rsplit_once(" ("))
+            .map(|(name, _)| name);
+
+        if let Some(name) = failed_name {
+            for result in results.iter_mut().filter(|result| result.name == name) {
+                result.status = Status::Failed;
+            }
+        }
+    }
+
+    results
+}
+
+// jest: `  ✓ adds (2 ms)`, `  ✕ subtracts (1 ms)` and `  ○ skipped divides`
+fn parse_jest(output: &str) -> Vec<TestResult> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+
+            let (status, name) = if let Some(name) = line.strip_prefix("✓ ") {
+                (Status::Passed, name)
+            } else if let Some(name) = line.strip_prefix("✕ ") {
+                (Status::Failed, name)
+            } else {
+                let name = line.strip_prefix("○ ")?;
+                let name = name
+                    .strip_prefix("skipped ")
+                    .or_else(|| name.strip_prefix("todo "))
+                    .unwrap_or(name);
+                (Status::Skipped, name)
+            };
+
+            // The duration is only shown for slow tests
+            let name = match name.rsplit_once(" (") {
+                Some((name, duration)) if duration.ends_with(" ms)") => name,
+                _ => name,
+            };
+
+            Some(to_test_result(name, status))
+        })
+        .collect()
+}
+
+fn to_test_result(name: &str, status: Status) -> TestResult {
+    TestResult {
+        name: name.to_string(),
+        status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_on_stderr() {
+        let stdout = "ok 1 - from tap\n";
+        let stderr = "PASS ./sum.test.js\n  ✓ adds (2 ms)\n  ✕ subtracts\n  ○ skipped divides\n";
+
+        let results = parse(&Language::JavaScript, stdout, stderr)
+            .into_iter()
+            .map(|result| (result.name, result.status))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec![
+                ("from tap".to_string(), Status::Passed),
+                ("adds".to_string(), Status::Passed),
+                ("subtracts".to_string(), Status::Failed),
+                ("divides".to_string(), Status::Skipped),
+            ]
+        );
+    }
+}
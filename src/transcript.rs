@@ -0,0 +1,39 @@
+use serde_json::json;
+use std::time;
+
+pub const FILE_NAME: &str = "transcript.cast";
+
+const WIDTH: u32 = 80;
+const HEIGHT: u32 = 24;
+
+pub struct Event<'a> {
+    pub elapsed: time::Duration,
+    pub content: &'a str,
+}
+
+// asciinema v2: a json header line followed by a `[seconds, "o", data]` line per event.
+// stdout and stderr are both terminal output, newlines get a carriage return like
+// a terminal would add, so players don't render the lines as a staircase
+pub fn to_asciicast(events: &[Event], started: time::SystemTime) -> String {
+    let timestamp = started
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let header = json!({
+        "version": 2,
+        "width": WIDTH,
+        "height": HEIGHT,
+        "timestamp": timestamp,
+    });
+
+    let mut lines = vec![header.to_string()];
+
+    for event in events {
+        let content = event.content.replace("\r\n", "\n").replace('\n', "\r\n");
+        let line = json!([event.elapsed.as_secs_f64(), "o", content]);
+        lines.push(line.to_string());
+    }
+
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,198 @@
+// Minimal ZMTP 3.0 (the ZeroMQ wire protocol) with the NULL security mechanism.
+// Only what's needed to talk to ZeroMQ peers over tcp is implemented:
+// the greeting, the READY handshake and multipart messages
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+const GREETING_LENGTH: usize = 64;
+
+// Exchange greetings and READY commands with the peer
+pub fn handshake<S: Read + Write>(stream: &mut S, socket_type: &str) -> io::Result<()> {
+    stream.write_all(&greeting())?;
+
+    let mut peer_greeting = [0u8; GREETING_LENGTH];
+    stream.read_exact(&mut peer_greeting)?;
+
+    if peer_greeting[0] != 0xff || peer_greeting[9] != 0x7f || peer_greeting[10] < 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unsupported ZMTP version",
+        ));
+    }
+
+    write_frame(stream, FLAG_COMMAND, &ready_command(socket_type))?;
+
+    // The peer's READY command, its properties are not needed
+    let (flags, _) = read_frame(stream)?;
+
+    if flags & FLAG_COMMAND == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Expected READY command",
+        ));
+    }
+
+    Ok(())
+}
+
+// Read the frames of the next message, commands from the peer are skipped
+pub fn read_message<R: Read>(stream: &mut R) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = vec![];
+
+    loop {
+        let (flags, body) = read_frame(stream)?;
+
+        if flags & FLAG_COMMAND != 0 {
+            continue;
+        }
+
+        frames.push(body);
+
+        if flags & FLAG_MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+pub fn write_message<W: Write>(stream: &mut W, frames: &[Vec<u8>]) -> io::Result<()> {
+    let mut buffer = vec![];
+
+    for (index, frame) in frames.iter().enumerate() {
+        let flags = if index + 1 < frames.len() {
+            FLAG_MORE
+        } else {
+            0
+        };
+        write_frame(&mut buffer, flags, frame)?;
+    }
+
+    stream.write_all(&buffer)?;
+    stream.flush()
+}
+
+fn greeting() -> [u8; GREETING_LENGTH] {
+    let mut greeting = [0u8; GREETING_LENGTH];
+
+    // Signature
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+
+    // Version
+    greeting[10] = 3;
+    greeting[11] = 0;
+
+    // Mechanism, as-server and the filler are left as zeros
+    greeting[12..16].copy_from_slice(b"NULL");
+
+    greeting
+}
+
+fn ready_command(socket_type: &str) -> Vec<u8> {
+    let mut command = vec![];
+
+    command.push(5);
+    command.extend_from_slice(b"READY");
+
+    command.push(11);
+    command.extend_from_slice(b"Socket-Type");
+    command.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    command.extend_from_slice(socket_type.as_bytes());
+
+    command
+}
+
+fn read_frame<R: Read>(stream: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0u8; 1];
+    stream.read_exact(&mut flags)?;
+
+    let size = if flags[0] & FLAG_LONG != 0 {
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size)?;
+        u64::from_be_bytes(size)
+    } else {
+        let mut size = [0u8; 1];
+        stream.read_exact(&mut size)?;
+        size[0] as u64
+    };
+
+    let mut body = vec![];
+    stream.by_ref().take(size).read_to_end(&mut body)?;
+
+    if (body.len() as u64) < size {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+
+    Ok((flags[0], body))
+}
+
+fn write_frame<W: Write>(stream: &mut W, flags: u8, body: &[u8]) -> io::Result<()> {
+    if body.len() > 255 {
+        stream.write_all(&[flags | FLAG_LONG])?;
+        stream.write_all(&(body.len() as u64).to_be_bytes())?;
+    } else {
+        stream.write_all(&[flags, body.len() as u8])?;
+    }
+
+    stream.write_all(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_short_frames() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &[b"a".to_vec(), b"bc".to_vec()]).unwrap();
+
+        assert_eq!(buffer, b"\x01\x01a\x00\x02bc");
+    }
+
+    #[test]
+    fn write_long_frame() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &[vec![b'x'; 256]]).unwrap();
+
+        assert_eq!(buffer[..9], [0x02, 0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(buffer[9..], [b'x'; 256]);
+    }
+
+    #[test]
+    fn read_message_skips_commands() {
+        let mut input: &[u8] = b"\x04\x05READY\x01\x01a\x02\x00\x00\x00\x00\x00\x00\x00\x02bc";
+
+        assert_eq!(
+            read_message(&mut input).unwrap(),
+            vec![b"a".to_vec(), b"bc".to_vec()]
+        );
+    }
+
+    #[test]
+    fn read_truncated_frame() {
+        let mut input: &[u8] = b"\x00\x05abc";
+
+        assert_eq!(
+            read_message(&mut input).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn greeting_and_ready_command() {
+        let greeting = greeting();
+
+        assert_eq!(greeting[..12], [0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0x7f, 3, 0]);
+        assert_eq!(&greeting[12..16], b"NULL");
+        assert!(greeting[16..].iter().all(|byte| *byte == 0));
+
+        assert_eq!(
+            ready_command("ROUTER"),
+            b"\x05READY\x0bSocket-Type\x00\x00\x00\x06ROUTER"
+        );
+    }
+}